@@ -0,0 +1,50 @@
+//! Measures sprite drawing throughput by running a ROM which
+//! draws 15 line sprites in a loop, presenting once per frame.
+//!
+//! Run with `cargo run --release --example draw_benchmark`.
+
+extern crate super_chip8;
+
+use std::time::Instant;
+use super_chip8::{CPU, Quirks, rom};
+use super_chip8::system::graphics::graphics_null::NullScreen;
+use super_chip8::system::io::mock_io::MockIO;
+
+const DRAWS : u64 = 1000000;
+const DRAWS_PER_FRAME : u64 = 100;
+
+fn run(hires: bool) {
+    let program = [
+        if hires {0x00} else {0x60}, if hires {0xFF} else {0x00}, /* 00FF or no-op */
+        0xA0, 0x00, /* I = font */
+        0xD0, 0x1F, /* draw 15 lines at V0, V1 */
+        0x70, 0x03, /* V0 += 3 */
+        0x71, 0x05, /* V1 += 5 */
+        0x12, 0x04  /* jump to draw */
+    ];
+    let mem = rom::load_rom(&program, rom::MAX_RAM).unwrap();
+    let quirks = Quirks { clip_sprites: false, .. Quirks::default() };
+    let mut cpu = CPU::new(mem, quirks, Box::new(NullScreen::new()), Box::new(MockIO::new()));
+    cpu.perform_cycle();
+    cpu.perform_cycle();
+
+    let start = Instant::now();
+    for draw in 0 .. DRAWS {
+        for _ in 0 .. 4 {
+            cpu.perform_cycle();
+        }
+        if draw % DRAWS_PER_FRAME == 0 {
+            cpu.present();
+        }
+    }
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+
+    println!("{}: {} sprites in {:.3}s, {:.0} sprites/s",
+             if hires {"hires"} else {"lores"}, DRAWS, secs, DRAWS as f64 / secs);
+}
+
+fn main() {
+    run(false);
+    run(true);
+}
@@ -0,0 +1,34 @@
+//! CHIP 8 interpreter with extended Super CHIP 8 functionality.
+//!
+//! The core of the emulator is `CPU`, which executes programs
+//! loaded with `rom::read_rom` or `rom::load_rom`. Output is rendered
+//! through the `Display` trait and keypad state is read through the
+//! `Input` trait, so the interpreter can be embedded without SDL:
+//!
+//! ```
+//! use super_chip8::{CPU, Quirks};
+//! use super_chip8::rom::{self, MAX_RAM};
+//! use super_chip8::system::graphics::graphics_null::NullScreen;
+//! use super_chip8::system::io::mock_io::MockIO;
+//!
+//! /* 6A42: load 0x42 into register A */
+//! let mem = rom::load_rom(&[0x6A, 0x42], MAX_RAM).unwrap();
+//! let mut cpu = CPU::new(mem, Quirks::default(),
+//!                        Box::new(NullScreen::new()), Box::new(MockIO::new()));
+//! cpu.perform_cycle();
+//! assert_eq!(cpu.get_reg(0xA), 0x42);
+//! ```
+//!
+//! The emulated display can be read back with `CPU::framebuffer` to
+//! write other renderers.
+//!
+//! The SDL window and keyboard frontends used by the `schip8` binary
+//! are only built with the `sdl` feature, which is enabled by default.
+
+pub mod system;
+pub mod rom;
+
+pub use system::{CPU, CpuError, StepOutcome, InvalidOpcodePolicy, Quirks, Platform, Profile};
+pub use system::graphics::{Graphics, Display, Resolution};
+pub use system::io::{IO, Input};
+pub use system::random::RandomSource;
@@ -1,103 +1,385 @@
-extern crate time;
+extern crate super_chip8;
+extern crate rand;
 
-use std::fs::File; /* input/output */
-use std::io::Read;
-use std::string::String;
+use std::collections::HashSet;
 use std::env;
-use system::CPU;
-use std::iter;
-use std::path::Path;
-use std::thread;
-
-mod system;
-
-
-static MAX_RAM : usize = 0x1000;
-static START_RAM :usize = 0x200;
-
-static INSTRUCTIONS_PER_SEC : u64 = 500;
-static CYCLES_CHECK : u64 = 5;
-
-/* Reads a ROM file and returns a vector containing
- * its bytes if successful. Otherwise an error string
- * if the file is too large or IoError is raised */
-fn read_rom(file_path: String) -> Result<Vec<u8>,String> {
-    
-    match File::open(Path::new(&file_path)) {
-        
-        Ok(f) => {
-            let rom_contents = f.bytes();
-
-            /* Programs start at address 0x200, in original implementation
-             * 0x000 - 0x1FF reserved for VM, just pad start of mem with 
-             * 0s in this case */
-            let start_mem : Vec<u8>  = iter::repeat(0u8).take(START_RAM).collect();
-            
-            let mut mem = start_mem;
-            for i in rom_contents {
-                match i {
-                    Ok(j) => mem.push(j),
-                    Err(_) => panic!{"Error reading file\n"}
-                }
-            }
-            let size = mem.len();
-
-            if size <= MAX_RAM { 
-                    Ok(mem)                    
-            } else { /* Memory read in from game ROM is too large */
-                Err(format!("game image is too large 
-                    ({} bytes), must be a maximum of {} bytes"
-                    ,size , MAX_RAM - START_RAM).to_string())
-            }        
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use super_chip8::{CPU, InvalidOpcodePolicy, Platform, StepOutcome, rom};
+use super_chip8::system::scheduler::{Scheduler, SystemClock};
+use super_chip8::system::graphics::graphics_sdl;
+use super_chip8::system::io::{self, sdl_io};
+use super_chip8::system::io::sdl_io::{Hotkeys, SlotHotkey};
+use super_chip8::system::rewind::RewindBuffer;
+use super_chip8::system::movie::{Movie, MovieRecorder, MoviePlayer};
+use super_chip8::system::audio::{Beeper, BeeperConfig, PatternGenerator, SampleGenerator,
+                                 Sound, Waveform, DEFAULT_SAMPLE_RATE};
+use super_chip8::system::audio::audio_sdl::AudioOutput;
+use super_chip8::system::audio::wav::AudioRecorder;
+
+
+static USAGE : &str = 
+    "Usage: schip8 [--platform platform] [--ips instructions_per_second] \
+     [--frequency hz] [--volume 0-100] [--waveform square|triangle|sawtooth|sine] \
+     [--mute] [--wav file] [--seed n] [--record movie | --play movie] [game]";
+
+/* Rewind through the last 30 seconds, snapshotting every other frame */
+const REWIND_SNAPSHOTS : usize = 900;
+const REWIND_INTERVAL : u64 = 2;
+
+
+/* Save states are kept next to the ROM, a file per slot */
+fn state_file(rom_file: &str, slot: usize) -> String {
+    format!("{}.state{}", rom_file, slot)
+}
+
+/* Rewinding after loading a state would jump back to the
+ * play it replaced, so the rewind history is dropped */
+fn handle_hotkey(chip8: &mut CPU, rewind: &mut RewindBuffer, hotkey: SlotHotkey, rom_file: &str) {
+    match hotkey {
+        SlotHotkey::Save(slot) => match chip8.save_state_file(state_file(rom_file, slot)) {
+            Ok(()) => println!("Saved state to slot {}", slot),
+            Err(e) => eprintln!("Failed to save slot {}: {}", slot, e)
         },
-             
-        Err(e) => Err(e.to_string())
+        SlotHotkey::Load(slot) => match chip8.load_state_file(state_file(rom_file, slot)) {
+            Ok(()) => {
+                rewind.clear();
+                println!("Loaded state from slot {}", slot);
+            },
+            Err(e) => eprintln!("Failed to load slot {}: {}", slot, e)
+        }
     }
-} 
-
-
-fn wait_for_next_cycle(old_time:u64, instructions:u64, ins_per_sec:u64 )  {
-    let current_time = time::precise_time_ns()/1000000;
-    if old_time < current_time {
-        let expired_ms = current_time - old_time;    
-        let overall_duration_ms = (1000 * instructions)/ins_per_sec; /*Calculate duration instruction should take */
-        if overall_duration_ms > expired_ms { /* ensure that duration has expired until next cycles begin */
-            thread::sleep_ms((overall_duration_ms - expired_ms) as u32);            
-       }
-    } 
 }
 
 
-fn run_program(mut chip8 :system::CPU, cycle_max: u64, ins_per_sec: u64)  {
-    
-    'run : loop {
-        let start_timer = time::precise_time_ns()/1000000;
-        for _ in (0 .. cycle_max) {
-            chip8.perform_cycle();
-            /* Check if execution is finished */
-            if chip8.is_finished() {
-                break 'run;
+/* SCHIP HP48 flags persist between runs as on the HP48, in a file
+ * per ROM keyed by the hash of its contents. They're saved whenever
+ * the program changes them so they survive however it's exited */
+struct FlagStore {
+    file: PathBuf,
+    saved: Vec<u8>
+}
+
+impl FlagStore {
+
+    fn new(rom_contents: &[u8]) -> FlagStore {
+        let dir = env::var_os("HOME").map_or(PathBuf::from("."), 
+                                            |home| Path::new(&home).join(".schip8"));
+        FlagStore { file: dir.join(format!("{:016x}.flags", rom::rom_hash(rom_contents))),
+                    saved: Vec::new() }
+    }
+
+    fn load(&mut self, chip8: &mut CPU) {
+        match fs::read(&self.file) {
+            Ok(flags) => chip8.set_hp_48_flags(&flags),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => eprintln!("Failed to read {}: {}", self.file.display(), e)
+        }
+        self.saved = chip8.hp_48_flags().to_vec();
+    }
+
+    fn update(&mut self, chip8: &CPU) {
+        if chip8.hp_48_flags() == &self.saved[..] {
+            return;
+        }
+        self.saved = chip8.hp_48_flags().to_vec();
+        let result = match self.file.parent() {
+            Some(dir) => fs::create_dir_all(dir),
+            None => Ok(())
+        }.and_then(|_| fs::write(&self.file, &self.saved));
+        if let Err(e) = result {
+            eprintln!("Failed to write {}: {}", self.file.display(), e);
+        }
+    }
+}
+
+
+/* Where keypad input comes from. While a movie is recorded the
+ * keyboard is sampled once per frame and fed to the CPU through
+ * the recorder, so playback sees exactly the same keys */
+enum MovieSession {
+    Off,
+    Recording(MovieRecorder, sdl_io::IOImpl, String),
+    Playing(MoviePlayer, bool) /* Set once a desync has been reported */
+}
+
+
+/* Run the program at 60 frames a second until it exits or Escape
+ * is pressed, playing or recording the sound of each frame. Quitting
+ * returns normally so main still writes out the recordings. While the
+ * rewind key is held the game plays backwards instead, save
+ * states and rewinding are disabled during movies */
+fn run_program(mut chip8 :CPU, rom_file: &str, ins_per_sec: u64, mut audio: Option<AudioOutput>,
+               mut recorder: Option<AudioRecorder>, mut flags: Option<FlagStore>,
+               mut movie: MovieSession) -> (Option<AudioRecorder>, MovieSession) {
+    let mut scheduler = Scheduler::new(ins_per_sec, Box::new(SystemClock::new()));
+    let mut hotkeys = Hotkeys::new();
+    let mut rewind = RewindBuffer::new(REWIND_SNAPSHOTS, REWIND_INTERVAL);
+
+    loop {
+        /* Polled during movies too so Escape and closing the window work */
+        let hotkey = hotkeys.poll();
+        if sdl_io::quit_requested() {
+            break;
+        }
+
+        match movie {
+            MovieSession::Off => if let Some(hotkey) = hotkey {
+                handle_hotkey(&mut chip8, &mut rewind, hotkey, rom_file);
+            },
+            MovieSession::Recording(ref mut movie_recorder, ref mut keyboard, _) =>
+                movie_recorder.start_frame(keyboard.keypad_state()),
+            MovieSession::Playing(ref mut player, _) => if !player.start_frame() {
+                println!("Movie finished");
+                break;
+            }
+        }
+
+        let rewinding = match movie {
+            MovieSession::Off => hotkeys.rewind_held(),
+            _ => false
+        };
+        if rewinding {
+            if let Err(e) = rewind.rewind_frame(&mut chip8) {
+                eprintln!("Failed to rewind: {}", e);
+            }
+            chip8.present();
+            if let Some(ref mut audio) = audio {
+                audio.update(Sound::default());
             }
+            scheduler.wait_for_next_frame();
+            continue;
+        }
+
+        let outcome = scheduler.run_instructions(&mut chip8);
+        if let Some(ref mut audio) = audio {
+            audio.update(chip8.sound());
+        }
+        if let Some(ref mut recorder) = recorder {
+            recorder.record_frame(&chip8.sound());
+        }
+        scheduler.end_frame(&mut chip8);
+        if let Some(ref mut flags) = flags {
+            flags.update(&chip8);
+        }
+
+        match movie {
+            MovieSession::Off => rewind.record_frame(&chip8),
+            MovieSession::Recording(ref mut movie_recorder, _, _) => 
+                movie_recorder.end_frame(&chip8),
+            MovieSession::Playing(ref mut player, ref mut desynced) => 
+                if let Err(e) = player.end_frame(&chip8) {
+                    if !*desynced {
+                        eprintln!("{}", e);
+                        *desynced = true;
+                    }
+                }
+        }
+
+        match outcome {
+            Ok(StepOutcome::Halted) => break,
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("Execution stopped: {}", e);
+                break;
+            }
+        }
+        scheduler.wait_for_next_frame();
+    }
+    (recorder, movie)
+}
+
+
+/* XO-CHIP programs play their own audio pattern, 
+ * other platforms have a simple beeper */
+fn sample_generator(platform: Platform, beeper: BeeperConfig) -> Box<dyn SampleGenerator + Send> {
+    if platform.profile().xo_chip_instructions {
+        Box::new(PatternGenerator::new(DEFAULT_SAMPLE_RATE))
+    } else {
+        Box::new(Beeper::new(DEFAULT_SAMPLE_RATE, beeper))
+    }
+}
+
+fn open_audio(platform: Platform, beeper: BeeperConfig) -> Option<AudioOutput> {
+    if beeper.muted {
+        return None;
+    }
+
+    match AudioOutput::open(sample_generator(platform, beeper), DEFAULT_SAMPLE_RATE) {
+        Ok(audio) => Some(audio),
+        Err(e) => {
+            eprintln!("{}, continuing without sound", e);
+            None
         }
-        wait_for_next_cycle(start_timer, cycle_max, ins_per_sec);
     }
 }
 
 
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, USAGE);
+    process::exit(1);
+}
+
+
 fn main() {
-    let args = env::args();   
+    let mut args = env::args().skip(1);
+    let mut file_name = None;
+    let mut platform = Platform::default();
+    let mut ins_per_sec = None;
+    let mut beeper = BeeperConfig::default();
+    let mut wav_file = None;
+    let mut seed = None;
+    let mut record_file = None;
+    let mut play_file = None;
 
-    let file_name = match args.skip(1).next() {
-        Some(os_f) => os_f,
-        None => panic!("Expected ROM file")
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--platform" => platform = match args.next().and_then(|p| Platform::from_name(&p)) {
+                Some(p) => p,
+                None => usage_error(&format!("--platform expects one of: {}", 
+                                             Platform::names().join(", ")))
+            },
+            "--ips" => ins_per_sec = match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => Some(n),
+                _ => usage_error("--ips expects a positive number")
+            },
+            "--frequency" => beeper.frequency = match args.next().and_then(|n| n.parse().ok()) {
+                Some(f) if f > 0.0 => f,
+                _ => usage_error("--frequency expects a positive number")
+            },
+            "--volume" => beeper.volume = match args.next().and_then(|n| n.parse::<u8>().ok()) {
+                Some(v) if v <= 100 => v as f64 / 100.0,
+                _ => usage_error("--volume expects a number from 0 to 100")
+            },
+            "--waveform" => beeper.waveform = match args.next().and_then(|w| Waveform::from_name(&w)) {
+                Some(w) => w,
+                None => usage_error("--waveform expects one of: square, triangle, sawtooth, sine")
+            },
+            "--mute" => beeper.muted = true,
+            "--wav" => wav_file = match args.next() {
+                Some(f) => Some(f),
+                None => usage_error("--wav expects a file name")
+            },
+            "--seed" => seed = match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => Some(n),
+                None => usage_error("--seed expects a number")
+            },
+            "--record" => record_file = match args.next() {
+                Some(f) => Some(f),
+                None => usage_error("--record expects a file name")
+            },
+            "--play" => play_file = match args.next() {
+                Some(f) => Some(f),
+                None => usage_error("--play expects a file name")
+            },
+            _ => file_name = Some(arg)
+        }
+    }
+
+    let file_name = match file_name {
+        Some(f) => f,
+        None => usage_error("Expected ROM file")
     };
 
-    let memory = match read_rom(file_name) {
+    if record_file.is_some() && play_file.is_some() {
+        usage_error("--record and --play can't be used together");
+    }
+
+    /* Movies replay with the settings they were recorded with */
+    let playing = play_file.map(|play_file| match Movie::load(&play_file) {
+        Ok(movie) => {
+            platform = movie.platform;
+            ins_per_sec = Some(movie.instructions_per_second);
+            MoviePlayer::new(movie)
+        },
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", play_file, e);
+            process::exit(1);
+        }
+    });
+
+    let profile = platform.profile();
+
+    let memory = match rom::read_rom(&file_name, profile.memory_size) {
         Ok(mem) => mem,
         Err(e) => panic!("{}",e)
     };
-    
-    assert!(memory.len() <=  MAX_RAM);
 
-    run_program(system::CPU::new(memory), CYCLES_CHECK, INSTRUCTIONS_PER_SEC);
+    let screen = graphics_sdl::Screen::new(256, 128, 64, 32);
+    let keyboard = sdl_io::IOImpl::new(io::DEFAULT_KEYS);
+
+    let ins_per_sec = ins_per_sec.unwrap_or(profile.instructions_per_second);
+
+    /* Sound is recorded instead of played when writing a WAV file */
+    let (audio, recorder) = match wav_file {
+        Some(_) => (None, Some(AudioRecorder::new(sample_generator(platform, beeper), 
+                                                  DEFAULT_SAMPLE_RATE))),
+        None => (open_audio(platform, beeper), None)
+    };
+
+    let rom_contents = memory[rom::START_RAM ..].to_vec();
+
+    if let Some(ref player) = playing {
+        if let Err(e) = player.movie().check_rom(&rom_contents) {
+            eprintln!("Can't play {}: {}", file_name, e);
+            process::exit(1);
+        }
+    }
+
+    let (mut chip8, movie) = match (playing, record_file) {
+        (Some(player), _) => 
+            (player.create_cpu(memory, Box::new(screen)), MovieSession::Playing(player, false)),
+        (None, Some(record_file)) => {
+            let movie_recorder = MovieRecorder::new(platform, ins_per_sec, 
+                                                    seed.unwrap_or_else(rand::random),
+                                                    rom::rom_hash(&rom_contents));
+            (movie_recorder.create_cpu(memory, Box::new(screen)),
+             MovieSession::Recording(movie_recorder, keyboard, record_file))
+        },
+        (None, None) => {
+            let mut chip8 = CPU::with_platform(memory, platform, Box::new(screen), 
+                                               Box::new(keyboard));
+            if let Some(seed) = seed {
+                chip8.seed_random(seed);
+            }
+            (chip8, MovieSession::Off)
+        }
+    };
+
+    /* Instructions which can't be decoded, such as 0NNN machine code
+     * calls, are skipped rather than stopping the program. Each is
+     * reported the first time it's seen */
+    let mut reported = HashSet::new();
+    chip8.set_invalid_opcode_policy(InvalidOpcodePolicy::Trap(Box::new(move |opcode, pc| {
+        if reported.insert((opcode, pc)) {
+            eprintln!("Skipping unknown opcode {:04X} at {:03X}", opcode, pc);
+        }
+    })));
+
+    /* Movies always start with cleared flags so they replay the same */
+    let flags = match movie {
+        MovieSession::Off => {
+            let mut flags = FlagStore::new(&rom_contents);
+            flags.load(&mut chip8);
+            Some(flags)
+        },
+        _ => None
+    };
+
+    let (recorder, movie) = run_program(chip8, &file_name, ins_per_sec, audio, recorder, 
+                                        flags, movie);
+
+    if let MovieSession::Recording(movie_recorder, _, record_file) = movie {
+        if let Err(e) = movie_recorder.movie().save(&record_file) {
+            eprintln!("Failed to write {}: {}", record_file, e);
+        }
+    }
+
+    if let (Some(recorder), Some(wav_file)) = (recorder, wav_file) {
+        if let Err(e) = recorder.save_wav(&wav_file) {
+            eprintln!("Failed to write {}: {}", wav_file, e);
+        }
+    }
 }
@@ -0,0 +1,98 @@
+//! Loading of CHIP 8 program images into an initial memory layout.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use system::hash;
+
+/// Total addressable memory of the CHIP 8 in bytes.
+pub const MAX_RAM : usize = 0x1000;
+
+/// Total addressable memory of XO-CHIP in bytes.
+pub const XO_MAX_RAM : usize = 0x10000;
+
+/// Address programs are loaded at, below this is reserved
+/// for the interpreter.
+pub const START_RAM : usize = 0x200;
+
+
+/// Builds a memory image from the raw bytes of a ROM.
+///
+/// The returned vector is padded with 0s up to `START_RAM` so it can be
+/// passed straight to `CPU::new`. Returns an error string if the ROM
+/// doesn't fit in `max_ram` bytes of memory.
+pub fn load_rom(rom_contents: &[u8], max_ram: usize) -> Result<Vec<u8>, String> {
+
+    /* Programs start at address 0x200, in original implementation
+     * 0x000 - 0x1FF reserved for VM, just pad start of mem with 
+     * 0s in this case */
+    let mut mem : Vec<u8> = vec![0u8; START_RAM];
+    mem.extend(rom_contents.iter().cloned());
+
+    let size = mem.len();
+
+    if size <= max_ram { 
+        Ok(mem)                    
+    } else { /* Memory read in from game ROM is too large */
+        Err(format!("game image is too large ({} bytes), must be a maximum of {} bytes",
+                    size - START_RAM, max_ram - START_RAM))
+    }        
+}
+
+
+/// Reads a ROM file and returns a memory image containing
+/// its bytes if successful. Otherwise an error string
+/// if the file is too large for `max_ram` bytes of memory
+/// or can't be read.
+pub fn read_rom<P: AsRef<Path>>(file_path: P, max_ram: usize) -> Result<Vec<u8>, String> {
+
+    let mut rom_contents = Vec::new();
+
+    match File::open(file_path.as_ref()) {
+        Ok(mut f) => match f.read_to_end(&mut rom_contents) {
+            Ok(_) => load_rom(&rom_contents, max_ram),
+            Err(e) => Err(format!("Error reading file: {}", e))
+        },
+        Err(e) => Err(e.to_string())
+    }
+} 
+
+
+/// 64 bit FNV-1a hash of a ROM's contents, identifies
+/// the ROM when saving data across runs.
+pub fn rom_hash(rom_contents: &[u8]) -> u64 {
+    hash::fnv1a(rom_contents)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{load_rom, rom_hash, MAX_RAM, XO_MAX_RAM, START_RAM};
+
+#[test]
+fn check_rom_loaded_at_start_address() {
+    let mem = load_rom(&[0x12, 0x34], MAX_RAM).unwrap();
+    assert_eq!(mem.len(), START_RAM + 2);
+    assert_eq!(mem[START_RAM], 0x12);
+    assert_eq!(mem[START_RAM + 1], 0x34);
+}
+
+#[test]
+fn check_rom_too_large() {
+    let rom = vec![0u8; MAX_RAM - START_RAM + 1];
+    assert!(load_rom(&rom, MAX_RAM).is_err());
+    assert!(load_rom(&rom[1 ..], MAX_RAM).is_ok());
+    assert!(load_rom(&rom, XO_MAX_RAM).is_ok());
+
+    let rom = vec![0u8; XO_MAX_RAM - START_RAM + 1];
+    assert!(load_rom(&rom, XO_MAX_RAM).is_err());
+}
+
+#[test]
+fn check_rom_hash() {
+    assert_eq!(rom_hash(&[]), 0xCBF29CE484222325);
+    assert_eq!(rom_hash(b"a"), 0xAF63DC4C8601EC8C);
+    assert!(rom_hash(&[0x12, 0x00]) != rom_hash(&[0x12, 0x02]));
+}
+
+}
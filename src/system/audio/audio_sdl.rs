@@ -0,0 +1,92 @@
+extern crate sdl;
+
+use std::sync::Mutex;
+use self::sdl::audio::{self, AudioFormat, Channels, DesiredAudioSpec};
+use super::{SampleGenerator, Sound};
+
+/* Samples per channel in each buffer requested by SDL */
+const BUFFER_SAMPLES : u16 = 1024;
+
+/* SDL's callback is a plain function so the generator and
+ * latest sound state are shared with it through a static */
+struct SharedAudio {
+    generator: Box<dyn SampleGenerator + Send>,
+    sound: Sound,
+    samples: Vec<i16> /* Reused between callbacks to avoid allocating */
+}
+
+static SHARED_AUDIO : Mutex<Option<SharedAudio>> = Mutex::new(None);
+
+
+fn fill_buffer(stream: &mut [u8]) {
+    let mut shared = SHARED_AUDIO.lock().unwrap_or_else(|e| e.into_inner());
+
+    match *shared {
+        Some(ref mut shared) => {
+            let sound = shared.sound;
+            shared.samples.resize(stream.len() / 2, 0);
+            shared.generator.generate(&sound, &mut shared.samples);
+
+            for (bytes, sample) in stream.chunks_mut(2).zip(shared.samples.iter()) {
+                bytes[0] = (*sample as u16 & 0xFF) as u8;
+                bytes[1] = (*sample as u16 >> 8) as u8;
+            }
+        },
+        None => {
+            for byte in stream.iter_mut() {
+                *byte = 0;
+            }
+        }
+    }
+}
+
+
+/// Plays the CPU's sound through the SDL audio device, only
+/// one can be open at a time.
+pub struct AudioOutput;
+
+impl AudioOutput {
+
+    /// Open the audio device and start playing samples from the
+    /// generator, returns an error if the device can't be opened.
+    pub fn open(generator: Box<dyn SampleGenerator + Send>,
+                sample_rate: u32) -> Result<AudioOutput, String> {
+        sdl::init(&[sdl::InitFlag::Audio]);
+
+        *SHARED_AUDIO.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some(SharedAudio { generator,
+                               sound: Sound::default(),
+                               samples: Vec::with_capacity(BUFFER_SAMPLES as usize) });
+
+        let spec = DesiredAudioSpec { freq: sample_rate as i32,
+                                      format: AudioFormat::S16LsbAudioFormat,
+                                      channels: Channels::Mono,
+                                      samples: BUFFER_SAMPLES,
+                                      callback: fill_buffer };
+        match audio::open(spec) {
+            Ok(_) => {
+                audio::pause(false);
+                Ok(AudioOutput)
+            },
+            Err(_) => {
+                *SHARED_AUDIO.lock().unwrap_or_else(|e| e.into_inner()) = None;
+                Err("failed to open audio device".to_string())
+            }
+        }
+    }
+
+    /// Update the sound being played, should be called each frame.
+    pub fn update(&mut self, sound: Sound) {
+        if let Some(ref mut shared) = *SHARED_AUDIO.lock().unwrap_or_else(|e| e.into_inner()) {
+            shared.sound = sound;
+        }
+    }
+}
+
+impl Drop for AudioOutput {
+
+    fn drop(&mut self) {
+        audio::close();
+        *SHARED_AUDIO.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
@@ -0,0 +1,291 @@
+#[cfg(feature = "sdl")]
+pub mod audio_sdl;
+pub mod wav;
+
+use std::f64::consts::PI;
+
+/// Size in bytes of the XO-CHIP audio pattern buffer.
+pub const PATTERN_SIZE : usize = 16;
+
+/// Pitch at which the pattern plays at `PATTERN_BASE_RATE`.
+pub const DEFAULT_PITCH : u8 = 64;
+
+/// Bits of the pattern played per second at the default pitch.
+pub const PATTERN_BASE_RATE : f64 = 4000.0;
+
+/* Square wave played until a program loads its own pattern,
+ * 250Hz at the default pitch */
+pub const DEFAULT_PATTERN : [u8; PATTERN_SIZE] =
+    [0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+     0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+
+const AMPLITUDE : i16 = 8192;
+
+/// Sample rate used by default when rendering audio.
+pub const DEFAULT_SAMPLE_RATE : u32 = 44100;
+
+
+/// Sound output state of the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sound {
+    /// 128 1-bit samples, played from the MS bit of the first byte.
+    pub pattern: [u8; PATTERN_SIZE],
+    /// Playback rate is `PATTERN_BASE_RATE * 2^((pitch - 64) / 48)`.
+    pub pitch: u8,
+    /// Sound plays while the sound timer is non zero.
+    pub playing: bool
+}
+
+impl Sound {
+
+    /// Bits of the pattern played per second.
+    pub fn playback_rate(&self) -> f64 {
+        PATTERN_BASE_RATE * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    fn bit(&self, position:usize) -> bool {
+        let bit = position % (PATTERN_SIZE * 8);
+        self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+    }
+}
+
+impl Default for Sound {
+    fn default() -> Sound {
+        Sound { pattern: DEFAULT_PATTERN, pitch: DEFAULT_PITCH, playing: false }
+    }
+}
+
+
+/// Source of signed 16 bit mono samples for the CPU's sound
+/// state, independent of any audio backend.
+pub trait SampleGenerator {
+    /// Fill the buffer with samples of the given sound, silence
+    /// if it isn't playing. Playback continues from where the
+    /// previous call left off so buffers can be rendered in turn.
+    fn generate(&mut self, sound: &Sound, out: &mut [i16]);
+}
+
+
+/// Plays the XO-CHIP audio pattern at the rate set by its pitch.
+pub struct PatternGenerator {
+    sample_rate: u32,
+    position: f64 /* Position in the pattern in bits */
+}
+
+impl PatternGenerator {
+
+    pub fn new(sample_rate:u32) -> PatternGenerator {
+        PatternGenerator { sample_rate, position: 0.0 }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl SampleGenerator for PatternGenerator {
+
+    fn generate(&mut self, sound: &Sound, out: &mut [i16]) {
+        if !sound.playing {
+            self.position = 0.0;
+            for sample in out.iter_mut() {
+                *sample = 0;
+            }
+            return;
+        }
+
+        let step = sound.playback_rate() / self.sample_rate as f64;
+        for sample in out.iter_mut() {
+            *sample = if sound.bit(self.position as usize) {AMPLITUDE} else {-AMPLITUDE};
+            self.position = (self.position + step) % (PATTERN_SIZE * 8) as f64;
+        }
+    }
+}
+
+
+/// Shape of the tone played by the `Beeper`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine
+}
+
+impl Waveform {
+
+    /// Look up a waveform by its command line name, one of
+    /// "square", "triangle", "sawtooth" or "sine".
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None
+        }
+    }
+
+    /* value of the waveform from -1 to 1 at the 
+     * given phase, from 0 to 1 through the cycle */
+    fn value(&self, phase:f64) -> f64 {
+        match *self {
+            Waveform::Square => if phase < 0.5 {1.0} else {-1.0},
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin()
+        }
+    }
+}
+
+
+/// Settings of the `Beeper`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeeperConfig {
+    /// Frequency of the tone in Hz.
+    pub frequency: f64,
+    /// Volume from 0 (silent) to 1 (full scale).
+    pub volume: f64,
+    pub waveform: Waveform,
+    /// No sound is output while muted.
+    pub muted: bool
+}
+
+impl Default for BeeperConfig {
+    fn default() -> BeeperConfig {
+        BeeperConfig { frequency: 440.0, 
+                       volume: 0.25, 
+                       waveform: Waveform::Square, 
+                       muted: false }
+    }
+}
+
+
+/// Plays a fixed tone while the sound timer is non zero,
+/// as the buzzer of the original interpreters.
+pub struct Beeper {
+    sample_rate: u32,
+    config: BeeperConfig,
+    phase: f64 /* Position through the current cycle, from 0 to 1 */
+}
+
+impl Beeper {
+
+    pub fn new(sample_rate:u32, config:BeeperConfig) -> Beeper {
+        Beeper { sample_rate, config, phase: 0.0 }
+    }
+
+    pub fn config(&self) -> BeeperConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config:BeeperConfig) {
+        self.config = config;
+    }
+}
+
+impl SampleGenerator for Beeper {
+
+    fn generate(&mut self, sound: &Sound, out: &mut [i16]) {
+        if !sound.playing || self.config.muted {
+            self.phase = 0.0;
+            for sample in out.iter_mut() {
+                *sample = 0;
+            }
+            return;
+        }
+
+        let volume = self.config.volume.clamp(0.0, 1.0) * i16::MAX as f64;
+        let step = self.config.frequency / self.sample_rate as f64;
+        for sample in out.iter_mut() {
+            *sample = (self.config.waveform.value(self.phase) * volume) as i16;
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{PatternGenerator, Beeper, BeeperConfig, Waveform, Sound, SampleGenerator, 
+                AMPLITUDE};
+
+#[test]
+fn check_silent_when_not_playing() {
+    let mut generator = PatternGenerator::new(4000);
+    let mut samples = [1i16; 8];
+    generator.generate(&Sound::default(), &mut samples);
+    assert_eq!(samples, [0i16; 8]);
+}
+
+#[test]
+fn check_pattern_played_at_pitch() {
+    let mut sound = Sound { playing: true, .. Sound::default() };
+    sound.pattern[0] = 0xA0;
+    let (h, l) = (AMPLITUDE, -AMPLITUDE);
+
+    /* One bit per sample at the default pitch */
+    let mut generator = PatternGenerator::new(4000);
+    let mut samples = [0i16; 4];
+    generator.generate(&sound, &mut samples);
+    assert_eq!(samples, [h, l, h, l]);
+
+    /* 48 steps up doubles the rate */
+    sound.pitch = 112;
+    assert_eq!(sound.playback_rate(), 8000.0);
+    let mut generator = PatternGenerator::new(4000);
+    let mut samples = [0i16; 4];
+    generator.generate(&sound, &mut samples);
+    assert_eq!(samples, [h, h, l, l]);
+}
+
+#[test]
+fn check_playback_continues_between_buffers() {
+    let mut sound = Sound { playing: true, .. Sound::default() };
+    sound.pattern[0] = 0x0F;
+    let mut generator = PatternGenerator::new(4000);
+    let mut samples = [0i16; 4];
+    generator.generate(&sound, &mut samples);
+    assert_eq!(samples, [-AMPLITUDE; 4]);
+    generator.generate(&sound, &mut samples);
+    assert_eq!(samples, [AMPLITUDE; 4]);
+}
+
+#[test]
+fn check_beeper_square_wave() {
+    let playing = Sound { playing: true, .. Sound::default() };
+    let config = BeeperConfig { frequency: 1000.0, volume: 0.5, .. BeeperConfig::default() };
+    let mut beeper = Beeper::new(4000, config);
+    let mut samples = [0i16; 8];
+    beeper.generate(&playing, &mut samples);
+    let (h, l) = (i16::MAX / 2, -(i16::MAX / 2));
+    assert_eq!(samples, [h, h, l, l, h, h, l, l]);
+
+    beeper.generate(&Sound::default(), &mut samples);
+    assert_eq!(samples, [0i16; 8]);
+}
+
+#[test]
+fn check_beeper_mute_and_waveform() {
+    let playing = Sound { playing: true, .. Sound::default() };
+    let config = BeeperConfig { frequency: 1000.0, volume: 1.0, 
+                                waveform: Waveform::Triangle, muted: false };
+    let mut beeper = Beeper::new(4000, config);
+    let mut samples = [0i16; 4];
+    beeper.generate(&playing, &mut samples);
+    let max = i16::MAX;
+    assert_eq!(samples, [-max, 0, max, 0]);
+
+    beeper.set_config(BeeperConfig { muted: true, .. config });
+    beeper.generate(&playing, &mut samples);
+    assert_eq!(samples, [0i16; 4]);
+}
+
+#[test]
+fn check_waveform_names() {
+    assert_eq!(Waveform::from_name("sine"), Some(Waveform::Sine));
+    assert_eq!(Waveform::from_name("noise"), None);
+}
+
+}
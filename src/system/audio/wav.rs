@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use super::{SampleGenerator, Sound};
+use system::TIMER_FREQUENCY;
+
+
+/// Renders the sound of each emulated frame without an audio
+/// device, so the output can be saved as a WAV file.
+///
+/// Frames are rendered sample accurately, frame n ends at sample
+/// `n * sample_rate / 60` so rates which aren't a multiple of 60
+/// don't drift.
+pub struct AudioRecorder {
+    generator: Box<dyn SampleGenerator>,
+    sample_rate: u32,
+    frame_count: u64,
+    samples: Vec<i16>
+}
+
+impl AudioRecorder {
+
+    pub fn new(generator: Box<dyn SampleGenerator>, sample_rate: u32) -> AudioRecorder {
+        AudioRecorder { generator,
+                        sample_rate,
+                        frame_count: 0,
+                        samples: Vec::new() }
+    }
+
+    /// Render a frame of the given sound, should be called once
+    /// per frame with the sound before the timers are ticked.
+    pub fn record_frame(&mut self, sound: &Sound) {
+        self.frame_count += 1;
+        let end = (self.frame_count * self.sample_rate as u64 / TIMER_FREQUENCY) as usize;
+        let start = self.samples.len();
+        self.samples.resize(end, 0);
+        self.generator.generate(sound, &mut self.samples[start ..]);
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// All samples recorded so far.
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    /// Write the recorded samples as a 16 bit mono PCM WAV file.
+    pub fn write_wav<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let data_size = (self.samples.len() * 2) as u32;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data_size).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; /* PCM */
+        out.write_all(&1u16.to_le_bytes())?; /* Mono */
+        out.write_all(&self.sample_rate.to_le_bytes())?;
+        out.write_all(&(self.sample_rate * 2).to_le_bytes())?; /* Bytes per second */
+        out.write_all(&2u16.to_le_bytes())?; /* Bytes per sample */
+        out.write_all(&16u16.to_le_bytes())?; /* Bits per sample */
+
+        out.write_all(b"data")?;
+        out.write_all(&data_size.to_le_bytes())?;
+        for sample in self.samples.iter() {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Save the recorded samples to a WAV file.
+    pub fn save_wav<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(file_path)?);
+        self.write_wav(&mut out)?;
+        out.flush()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::AudioRecorder;
+    use system::Platform;
+    use system::audio::{Beeper, BeeperConfig};
+    use system::scheduler::{Scheduler, SystemClock};
+    use system::test_util::setup_cpu;
+
+fn setup_recorder(sample_rate: u32) -> AudioRecorder {
+    AudioRecorder::new(Box::new(Beeper::new(sample_rate, BeeperConfig::default())),
+                       sample_rate)
+}
+
+#[test]
+fn check_frames_are_sample_accurate() {
+    /* 1000 / 60 samples per frame isn't a whole number */
+    let mut recorder = setup_recorder(1000);
+    let sound = Default::default();
+    let mut lengths = Vec::new();
+    for _ in 0 .. 60 {
+        let start = recorder.samples().len();
+        recorder.record_frame(&sound);
+        lengths.push(recorder.samples().len() - start);
+    }
+    assert_eq!(&lengths[.. 3], &[16, 17, 17]);
+    assert_eq!(recorder.samples().len(), 1000);
+}
+
+#[test]
+fn check_sound_timer_recorded() {
+    /* V0 = 2, sound = V0, loop */
+    let mut cpu = setup_cpu(Platform::default(), &[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]);
+    let mut scheduler = Scheduler::new(600, Box::new(SystemClock::new()));
+    let mut recorder = setup_recorder(6000);
+
+    for _ in 0 .. 3 {
+        scheduler.run_instructions(&mut cpu).unwrap();
+        recorder.record_frame(&cpu.sound());
+        scheduler.end_frame(&mut cpu);
+    }
+
+    /* Sound plays for the 2 frames the timer is non zero */
+    let samples = recorder.samples();
+    assert_eq!(samples.len(), 300);
+    assert!(samples[.. 100].iter().any(|&s| s != 0));
+    assert!(samples[100 .. 200].iter().any(|&s| s != 0));
+    assert!(samples[200 ..].iter().all(|&s| s == 0));
+}
+
+#[test]
+fn check_wav_header() {
+    let mut recorder = setup_recorder(6000);
+    recorder.record_frame(&Default::default());
+    let mut wav = Vec::new();
+    recorder.write_wav(&mut wav).unwrap();
+
+    assert_eq!(wav.len(), 44 + 200);
+    assert_eq!(&wav[0 .. 4], b"RIFF");
+    assert_eq!(&wav[4 .. 8], &[236, 0, 0, 0]);
+    assert_eq!(&wav[24 .. 28], &[0x70, 0x17, 0, 0]); /* 6000Hz */
+    assert_eq!(&wav[36 .. 44], &[b'd', b'a', b't', b'a', 200, 0, 0, 0]);
+}
+
+}
@@ -0,0 +1,33 @@
+use std::error::Error;
+use std::fmt;
+
+/// Fault raised while executing an instruction. The program counter
+/// is left pointing at the faulting instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode at `pc` doesn't correspond to any instruction.
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// A subroutine call at `pc` exceeded the stack depth.
+    StackOverflow { pc: u16 },
+    /// A return at `pc` was executed with an empty stack.
+    StackUnderflow { pc: u16 },
+    /// An instruction tried to access memory past the end of RAM.
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::UnknownOpcode { pc, opcode } => 
+                write!(f, "Unknown opcode {:04X} at {:03X}", opcode, pc),
+            CpuError::StackOverflow { pc } => 
+                write!(f, "Stack overflow calling subroutine at {:03X}", pc),
+            CpuError::StackUnderflow { pc } =>
+                write!(f, "Stack underflow returning from subroutine at {:03X}", pc),
+            CpuError::MemoryOutOfBounds { addr } => 
+                write!(f, "Memory access out of bounds at {:X}", addr),
+        }
+    }
+}
+
+impl Error for CpuError {}
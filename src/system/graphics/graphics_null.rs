@@ -0,0 +1,32 @@
+use super::Display;
+
+/// Display which discards all output, allows the
+/// interpreter to run without a window e.g. in tests.
+pub struct NullScreen;
+
+impl NullScreen {
+
+    pub fn new() -> NullScreen {
+        NullScreen
+    }
+}
+
+impl Default for NullScreen {
+
+    fn default() -> NullScreen {
+        NullScreen::new()
+    }
+}
+
+impl Display for NullScreen {
+
+    fn set_x_max(&mut self, _:usize) {}
+
+    fn set_y_max(&mut self, _:usize) {}
+
+    fn draw_pix(&mut self, _ :isize, _ :isize, _:u8) {}
+
+    fn clear_screen(&mut self) {}
+
+    fn show(&mut self) {}
+}
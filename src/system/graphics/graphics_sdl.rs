@@ -1,12 +1,12 @@
 
 extern crate sdl;
 
-use self::sdl::video::{SurfaceFlag, VideoFlag};
+use self::sdl::video::SurfaceFlag;
+use super::Display;
 
 pub struct Screen {
     surface :sdl::video::Surface,
-    on_color : sdl::video::Color,
-    off_color :sdl::video::Color,
+    palette : [sdl::video::Color; 4],
     width: usize,
     height: usize,
     x_max :usize,
@@ -20,34 +20,47 @@ impl Screen {
         sdl::init(&[sdl::InitFlag::Video]);     
         sdl::wm::set_caption("CHIP-8 Emulator", "sdl");  
 
+        /* Only changed pixels are redrawn each frame, so the surface must
+         * keep its contents between flips which double buffering doesn't */
         let surface = 
             match sdl::video::set_video_mode(width, height, 32,
-                  &[SurfaceFlag::HWSurface],
-                  &[VideoFlag::DoubleBuf]) {
+                  &[SurfaceFlag::SWSurface],
+                  &[]) {
                 
                 Ok(screen) => screen,
                 Err(err) => panic!("failed to set video mode: {}", err)
             };
 
-        Screen { surface:surface, 
-                 on_color:  sdl::video::RGB(0, 255, 0), /* Green */ 
-                 off_color: sdl::video::RGB(0, 0,   0), /* Black */
+        Screen { surface, 
+                 palette: [sdl::video::RGB(0,   0,   0),  /* Black */
+                           sdl::video::RGB(0,   255, 0),  /* Green, first plane */
+                           sdl::video::RGB(255, 170, 0),  /* Amber, second plane */
+                           sdl::video::RGB(255, 255, 255) /* White, both planes */],
                  width:width as usize,
                  height:height as usize,
-                 x_max:x_max,
-                 y_max:y_max 
+                 x_max,
+                 y_max
                }
         }
+
+    /// Set the colours used for each palette index, index 0 is
+    /// the background and 1 the colour of the first plane.
+    pub fn set_palette(&mut self, palette: [sdl::video::Color; 4]) {
+        self.palette = palette;
+    }
+}
+
+impl Display for Screen {
   
-    pub fn set_x_max(&mut self, x:usize) {
+    fn set_x_max(&mut self, x:usize) {
         self.x_max = x;
     }
 
-    pub fn set_y_max(&mut self, y:usize) {
+    fn set_y_max(&mut self, y:usize) {
         self.y_max = y;
     }
 
-    pub fn draw_pix(&mut self, x_pos :isize, y_pos :isize, set:bool) {
+    fn draw_pix(&mut self, x_pos :isize, y_pos :isize, colour:u8) {
         let x_unit = (self.width/self.x_max) as u16;
         let y_unit = (self.height/self.y_max) as u16;
 
@@ -56,21 +69,21 @@ impl Screen {
             y: y_pos as i16 * y_unit as i16,
             w: x_unit,
             h: y_unit,
-        }), match set { true => self.on_color, false => self.off_color });
+        }), self.palette[(colour & 3) as usize]);
         
     }
 
-    pub fn clear_screen(&mut self) {
+    fn clear_screen(&mut self) {
         self.surface.fill_rect(Some(sdl::Rect {
             x: 0,
             y: 0,
             w: self.width as u16,
             h: self.height as u16,
-        }), self.off_color);   
+        }), self.palette[0]);   
     }
 
 
-    pub fn show(&mut self) {
+    fn show(&mut self) {
         
         self.surface.flip();
     }
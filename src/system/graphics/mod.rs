@@ -1,9 +1,33 @@
 
+#[cfg(feature = "sdl")]
 pub mod graphics_sdl;
+pub mod graphics_null;
+
+use std::cmp;
 
 const MAX_HORIZONTAL_PIXELS : usize = 128;
 const MAX_VERTICAL_PIXELS : usize = 64;
 
+/// Number of bitplanes, each pixel's colour is a 2 bit
+/// index into a 4 colour palette with a bit per plane.
+pub const PLANE_COUNT : usize = 2;
+/// Rows of each plane of the framebuffer, as indexed by `plane_row`.
+pub const FRAMEBUFFER_ROWS : usize = MAX_VERTICAL_PIXELS;
+const ALL_PLANES : u8 = (1 << PLANE_COUNT) - 1;
+
+/* Bit mask with a bit for every framebuffer row */
+const ALL_ROWS : u64 = !0;
+
+
+/// Display resolution selected by 00FE/00FF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// 64x32 CHIP 8 resolution.
+    Low,
+    /// 128x64 Super CHIP 8 resolution.
+    High
+}
+
 
 struct Dimensions {
     width:  usize,
@@ -31,131 +55,621 @@ fn get_height(mode : bool) -> usize {
 }
 
 
+/// Output device the framebuffer is rendered to, pixel
+/// coordinates are in terms of the current x_max/y_max resolution.
+/// Pixel colours are palette indices from 0 (background) to 3.
+pub trait Display {
+    fn set_x_max(&mut self, x:usize);
+    fn set_y_max(&mut self, y:usize);
+    fn draw_pix(&mut self, x_pos :isize, y_pos :isize, colour:u8);
+    fn clear_screen(&mut self);
+    fn show(&mut self);
+}
+
+
+/// Framebuffer of the emulated display, supporting both the
+/// 64x32 CHIP 8 and 128x64 Super CHIP 8 resolutions.
+///
+/// The framebuffer has two bitplanes as on XO-CHIP, drawing, scrolling
+/// and clearing only affect the selected planes. Only the first plane
+/// is selected unless a program selects others.
+///
+/// With doubled lores the framebuffer is always 128x64 as on SCHIP 1.x,
+/// low resolution pixels are drawn as 2x2 blocks so content drawn in
+/// either resolution stays on screen after switching modes.
+///
+/// Instructions only update the framebuffer, `show` renders it to the
+/// display and should be called once per frame. Only pixels which have
+/// changed since the last `show` are redrawn.
+///
+/// Each row of a plane is packed into a `u128` with the leftmost pixel
+/// in the most significant bit, so drawing, collision detection and
+/// scrolling are done a row at a time with shifts and masks.
 pub struct Graphics {
     mode :bool,
-    screen : [[bool; MAX_HORIZONTAL_PIXELS]; MAX_VERTICAL_PIXELS],
-    out : graphics_sdl::Screen
+    screen : [[u128; MAX_VERTICAL_PIXELS]; PLANE_COUNT],
+    shown : [[u128; MAX_VERTICAL_PIXELS]; PLANE_COUNT], /* As last rendered */
+    dirty_rows : u64, /* Bit mask of rows changed since last shown */
+    version : u64, /* Incremented whenever the framebuffer changes */
+    full_redraw : bool, /* Display needs redrawing from scratch */
+    planes : u8, /* Bit mask of selected planes */
+    doubled_lores : bool,
+    out : Box<dyn Display>
+}
+
+/* Bit of a packed row for the pixel in column x */
+fn column_bit(x:usize) -> u128 {
+    1u128 << (MAX_HORIZONTAL_PIXELS - 1 - x)
+}
+
+/* Mask of the leftmost width columns of a packed row */
+fn columns_mask(width:usize) -> u128 {
+    (!0u128).checked_shl((MAX_HORIZONTAL_PIXELS - width) as u32).unwrap_or(0)
+}
+
+/* Repeat each of the low bit_count bits of line twice */
+fn double_bits(line:u128, bit_count:usize) -> u128 {
+    let mut doubled = 0u128;
+    for i in 0 .. bit_count {
+        if line & (1 << i) != 0 {
+            doubled |= 3 << (2 * i);
+        }
+    }
+    doubled
+}
+
+/* Place the low bit_count bits of line in a row width columns wide
+ * starting at column x, bits past the right edge are dropped or
+ * wrapped around to the left edge. Width is either 64 or 128 */
+fn position_line(line:u128, bit_count:usize, x:usize, width:usize, clip:bool) -> u128 {
+    let line = line << (MAX_HORIZONTAL_PIXELS - bit_count);
+    if width == MAX_HORIZONTAL_PIXELS {
+        if clip {line >> x} else {line.rotate_right(x as u32)}
+    } else {
+        let line = (line >> 64) as u64;
+        let row = if clip {line >> x} else {line.rotate_right(x as u32)};
+        (row as u128) << 64
+    }
 }
 
 impl Graphics {
 
-    pub fn new() -> Graphics {
-        Graphics { mode: false,
+    pub fn new(out: Box<dyn Display>) -> Graphics {
+        let mut graphics = Graphics { mode: false,
                    /* Initialize all pixels to blank */
-                   screen: [[false; MAX_HORIZONTAL_PIXELS]; MAX_VERTICAL_PIXELS],
-                   out: graphics_sdl::Screen::new(256, 128, 
-                    CHIP_DIMENSIONS.width, CHIP_DIMENSIONS.height)
-         }
+                   screen: [[0u128; MAX_VERTICAL_PIXELS]; PLANE_COUNT],
+                   shown: [[0u128; MAX_VERTICAL_PIXELS]; PLANE_COUNT],
+                   dirty_rows: 0,
+                   version: 0,
+                   full_redraw: true,
+                   planes: 1,
+                   doubled_lores: false,
+                   out
+         };
+        graphics.set_mode(false);
+        graphics
     }
 
     pub fn set_mode(&mut self, new_mode:bool) { 
         self.mode = new_mode;
-        self.out.set_x_max(get_width(new_mode));
-        self.out.set_y_max(get_height(new_mode));
+        let (width, height) = (self.framebuffer_width(), self.framebuffer_height());
+        self.out.set_x_max(width);
+        self.out.set_y_max(height);
+        self.full_redraw = true;
+        self.version += 1;
+    }
+
+    /* record that the given framebuffer rows have changed */
+    fn touch(&mut self, rows:u64) {
+        self.dirty_rows |= rows;
+        self.version += 1;
+    }
+
+    /// Keep a 128x64 framebuffer in low resolution, drawing
+    /// each pixel as a 2x2 block.
+    pub fn set_doubled_lores(&mut self, doubled:bool) {
+        self.doubled_lores = doubled;
+        let mode = self.mode;
+        self.set_mode(mode);
+    }
+
+    pub fn doubled_lores(&self) -> bool {
+        self.doubled_lores
+    }
+
+    /// Size in framebuffer pixels of a pixel in the current resolution.
+    pub fn framebuffer_scale(&self) -> usize {
+        if self.doubled_lores && !self.mode {2} else {1}
+    }
+
+    fn framebuffer_width(&self) -> usize {
+        self.width() * self.framebuffer_scale()
+    }
+
+    fn framebuffer_height(&self) -> usize {
+        self.height() * self.framebuffer_scale()
     }
     
 
-    pub fn draw_pix(&mut self, x:usize, y:usize, state:bool) {
-        self.screen[y][x] = state;
+    /// Select which planes are affected by drawing, scrolling
+    /// and clearing, bit 0 is the first plane.
+    pub fn select_planes(&mut self, planes:u8) {
+        self.planes = planes & ALL_PLANES;
     }
 
+    /// Indices of each selected plane, in the order
+    /// sprite data for them is stored.
+    pub fn selected_planes(&self) -> SelectedPlanes {
+        SelectedPlanes { planes: self.planes, next: 0 }
+    }
 
-    /* Create a bit vector for the supplied number from
-     * MS bit to LS bit */
-    fn to_bit_vec(n:usize, bit_count:usize) -> Vec<u8> {
-              
-        let largest_bit : usize = 1 << (bit_count - 1); 
+    /// Bit mask of the selected planes, as passed to `select_planes`.
+    pub fn selected_plane_mask(&self) -> u8 {
+        self.planes
+    }
 
-        (0 .. bit_count)
-            .map(|idx| if n & (largest_bit >> idx ) != 0 {1u8} else {0u8}) 
-            .collect()           
+    pub fn selected_plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
     }
 
+    /* Set the selected planes of a pixel in the current
+     * resolution to those in the given colour, leaving
+     * other planes unchanged */
+    pub fn draw_pix(&mut self, x:usize, y:usize, colour:u8) {
+        let scale = self.framebuffer_scale();
+        let columns = position_line(if scale == 2 {3} else {1}, scale, x * scale,
+                                    MAX_HORIZONTAL_PIXELS, true);
+        for p in self.selected_planes() {
+            for fb_y in y * scale .. (y + 1) * scale {
+                let row = &mut self.screen[p][fb_y];
+                if colour & (1 << p) != 0 {
+                    *row |= columns;
+                } else {
+                    *row &= !columns;
+                }
+                self.touch(1 << fb_y);
+            }
+        }
+    }
 
     
-    pub fn draw_line(&mut self, startx:u8, starty:u8, line:usize, bits:usize) -> bool {       
+    /* Draw a line of a sprite at x,y xoring it with the contents
+     * of the plane with the given index, pixels past the edge of
+     * the screen are either clipped or wrapped around. Returns 
+     * true if any set pixel was unset */
+    pub fn draw_line(&mut self, startx:usize, starty:usize, line:usize, bits:usize,
+                     clip:bool, p:usize) -> bool {       
       
-       let mut unset_occured = false;
+        let (width, height) = (get_width(self.mode), get_height(self.mode));
+        let scale = self.framebuffer_scale();
 
-       let  pixel_states :Vec<bool>  = Graphics::to_bit_vec(line, bits)
-                       .iter()
-                       .map(|&x| if x == 0 {false} else {true})
-                       .collect();
-      
-        let current_states = &mut (self.screen[starty as usize % 64])[startx as usize ..];
-                        
-        
-        let zipped_states = current_states.iter_mut().zip(pixel_states.iter());
-        /* Set pixel to old pixel xor new pixel */
-        for (old, new) in zipped_states { 
-            if !unset_occured && *old == true && *new == true {
-                unset_occured = true;
-            } 
-            *old = *old ^ *new; 
+        if clip && starty >= height {
+            return false;
         }
+        let y = starty % height;
 
-        return unset_occured;;
-   }
+        let line = if scale == 2 {double_bits(line as u128, bits)} else {line as u128};
+        let sprite = position_line(line, bits * scale, (startx % width) * scale,
+                                   width * scale, clip);
+
+        let mut unset_occured = false;
+        for fb_y in y * scale .. (y + 1) * scale {
+            let row = &mut self.screen[p][fb_y];
+            unset_occured |= *row & sprite != 0;
+            *row ^= sprite;
+            self.touch(1 << fb_y);
+        }
+
+        unset_occured
+    }
        
 
+    /// Number of times the framebuffer or resolution has changed,
+    /// only ever increases so renderers can skip unchanged frames.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        if self.mode {Resolution::High} else {Resolution::Low}
+    }
+
+    /// Width in pixels of the current resolution.
+    pub fn width(&self) -> usize {
+        get_width(self.mode)
+    }
+
+    /// Height in pixels of the current resolution.
+    pub fn height(&self) -> usize {
+        get_height(self.mode)
+    }
+
+    /// Colour index of each pixel of row y in the current resolution.
+    pub fn row(&self, y:usize) -> Vec<u8> {
+        (0 .. self.width()).map(|x| self.pixel(x, y)).collect()
+    }
+
+    /// Row y of a plane as stored in the framebuffer, packed with the
+    /// leftmost pixel in the most significant bit. With doubled lores
+    /// the framebuffer is 128x64 in both resolutions.
+    pub fn plane_row(&self, plane:usize, y:usize) -> u128 {
+        self.screen[plane][y]
+    }
+
+    /// Replace row y of a plane with a packed row as returned by
+    /// `plane_row`, used to restore saved framebuffers.
+    pub fn set_plane_row(&mut self, plane:usize, y:usize, row:u128) {
+        self.screen[plane][y] = row;
+        self.touch(1 << y);
+    }
+
+
+    /// Colour index of the pixel at x,y in the current resolution,
+    /// with doubled lores the top left of its 2x2 block.
+    pub fn pixel(&self, x:usize, y:usize) -> u8 {
+        let scale = self.framebuffer_scale();
+        self.framebuffer_pixel(x * scale, y * scale)
+    }
+
+    fn framebuffer_pixel(&self, x:usize, y:usize) -> u8 {
+        (0 .. PLANE_COUNT)
+            .filter(|&p| self.screen[p][y] & column_bit(x) != 0)
+            .fold(0, |colour, p| colour | (1 << p))
+    }
+
+
+    /* Scrolling moves the contents of the selected planes by n 
+     * framebuffer pixels, pixels scrolled in are blank */
 
     pub fn scroll_right(&mut self, n:u8) {
-        let n = n as usize;
-        for y in 0 .. get_height(self.mode) {
-            for x in (get_width(self.mode) - 1 .. n  - 1) {
-                let set = self.screen[y][x - n];
-                self.draw_pix(x, y, set);            
-            }
-            for x in 0 .. n { 
-                self.draw_pix(x, y, false);
+        let visible = columns_mask(self.framebuffer_width());
+        for p in self.selected_planes() {
+            for y in 0 .. self.framebuffer_height() {
+                let row = &mut self.screen[p][y];
+                let scrolled = row.checked_shr(n as u32).unwrap_or(0);
+                *row = (*row & !visible) | (scrolled & visible);
             }
         }
+        self.touch(ALL_ROWS);
     }
 
     pub fn scroll_left(&mut self, n:u8) {
-        let x_max = get_width(self.mode);
-        let n = n as usize;
-        for y in 0  .. get_height(self.mode) {
-            for x in 0 .. (x_max - n) {
-                let set = self.screen[y][x + n];
-                self.draw_pix(x, y, set);
-            }
-            for x in (x_max - n .. x_max) {
-                self.draw_pix(x, y, false);
+        let visible = columns_mask(self.framebuffer_width());
+        for p in self.selected_planes() {
+            for y in 0 .. self.framebuffer_height() {
+                let row = &mut self.screen[p][y];
+                let scrolled = (*row & visible).checked_shl(n as u32).unwrap_or(0);
+                *row = (*row & !visible) | scrolled;
             }
         }
+        self.touch(ALL_ROWS);
     }
 
     pub fn scroll_down(&mut self, n:u8) {
-        let y_max = get_height(self.mode);
-        let n = n as usize;
-        for x in 0 .. get_width(self.mode) {
-            for y in(y_max - 1 .. n - 1) {                
-                let set = self.screen[y - n][x];
-                self.draw_pix(x, y, set);            
+        let visible = columns_mask(self.framebuffer_width());
+        let y_max = self.framebuffer_height();
+        let n = cmp::min(n as usize, y_max);
+        for p in self.selected_planes() {
+            let rows = &mut self.screen[p];
+            /* Move from the bottom so rows are 
+             * read before they're overwritten */
+            for y in (0 .. y_max).rev() {
+                let scrolled = if y >= n {rows[y - n] & visible} else {0};
+                rows[y] = (rows[y] & !visible) | scrolled;
             }
-            for y  in 0 .. n { 
-                self.draw_pix(x, y, false);
+        }
+        self.touch(ALL_ROWS);
+    }
+
+    pub fn scroll_up(&mut self, n:u8) {
+        let visible = columns_mask(self.framebuffer_width());
+        let y_max = self.framebuffer_height();
+        let n = n as usize;
+        for p in self.selected_planes() {
+            let rows = &mut self.screen[p];
+            for y in 0 .. y_max {
+                let scrolled = if y + n < y_max {rows[y + n] & visible} else {0};
+                rows[y] = (rows[y] & !visible) | scrolled;
             }
         }
+        self.touch(ALL_ROWS);
     }
 
+    /* clear all planes regardless of which are selected */
+    pub fn clear_all(&mut self) {
+        self.screen = [[0u128; MAX_VERTICAL_PIXELS]; PLANE_COUNT];
+        self.touch(ALL_ROWS);
+    }
+
+    /* clear the selected planes */
     pub fn clear_screen(&mut self) {
-        for y in 0 .. MAX_VERTICAL_PIXELS {
-            for x in 0 .. MAX_HORIZONTAL_PIXELS {
-                self.screen[y][x] = false;
-            }
+        for p in self.selected_planes() {
+            self.screen[p] = [0u128; MAX_VERTICAL_PIXELS];
         }
-        self.out.clear_screen();
+        self.touch(ALL_ROWS);
     }
     
-    //TODO improve as iinefficient (causes redrawing of entire screen)
+    /// Render pixels changed since the last call to the display and
+    /// present it, nothing is presented if the framebuffer is unchanged.
     pub fn show(&mut self) {
-        for y in 0 .. MAX_VERTICAL_PIXELS {
-            for x in 0 .. MAX_HORIZONTAL_PIXELS {
-                self.out.draw_pix(x as isize, y as isize, self.screen[y][x]);
+        let mut changed = self.full_redraw;
+        if self.full_redraw {
+            self.out.clear_screen();
+            self.shown = [[0u128; MAX_VERTICAL_PIXELS]; PLANE_COUNT];
+            self.dirty_rows = ALL_ROWS;
+            self.full_redraw = false;
+        }
+
+        let visible = columns_mask(self.framebuffer_width());
+        for y in 0 .. self.framebuffer_height() {
+            if self.dirty_rows & (1 << y) == 0 {
+                continue;
+            }
+
+            let mut diff = 0u128;
+            for p in 0 .. PLANE_COUNT {
+                diff |= self.screen[p][y] ^ self.shown[p][y];
+                self.shown[p][y] = self.screen[p][y];
+            }
+            diff &= visible;
+
+            /* Draw each changed pixel from left to right */
+            while diff != 0 {
+                let x = diff.leading_zeros() as usize;
+                let colour = self.framebuffer_pixel(x, y);
+                self.out.draw_pix(x as isize, y as isize, colour);
+                diff &= !column_bit(x);
+                changed = true;
+            }
+        }
+
+        self.dirty_rows = 0;
+        if changed {
+            self.out.show();
+        }
+    }
+}
+
+
+/// Iterator over the indices of the selected planes.
+pub struct SelectedPlanes {
+    planes: u8,
+    next: usize
+}
+
+impl Iterator for SelectedPlanes {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.next < PLANE_COUNT {
+            let plane = self.next;
+            self.next += 1;
+            if self.planes & (1 << plane) != 0 {
+                return Some(plane);
+            }
+        }
+        None
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Graphics, Display};
+    use super::graphics_null::NullScreen;
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+/* Display counting the pixels drawn and times shown */
+#[derive(Clone)]
+struct CountingScreen {
+    pixels_drawn: Rc<Cell<usize>>,
+    shown: Rc<Cell<usize>>
+}
+
+impl CountingScreen {
+    fn new() -> CountingScreen {
+        CountingScreen { pixels_drawn: Rc::new(Cell::new(0)), shown: Rc::new(Cell::new(0)) }
+    }
+}
+
+impl Display for CountingScreen {
+    fn set_x_max(&mut self, _:usize) {}
+    fn set_y_max(&mut self, _:usize) {}
+    fn draw_pix(&mut self, _ :isize, _ :isize, _:u8) {
+        self.pixels_drawn.set(self.pixels_drawn.get() + 1);
+    }
+    fn clear_screen(&mut self) {}
+    fn show(&mut self) {
+        self.shown.set(self.shown.get() + 1);
+    }
+}
+
+fn setup_graphics(hires: bool) -> Graphics {
+    let mut graphics = Graphics::new(Box::new(NullScreen::new()));
+    graphics.set_mode(hires);
+    graphics
+}
+
+/* Set pixels at the given coordinates */
+fn draw_pixels(graphics: &mut Graphics, pixels: &[(usize, usize)]) {
+    for &(x, y) in pixels {
+        graphics.draw_pix(x, y, 1);
+    }
+}
+
+/* Coordinates of all set pixels in the current resolution */
+fn set_pixels(graphics: &Graphics) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for y in 0 .. graphics.height() {
+        for x in 0 .. graphics.width() {
+            if graphics.pixel(x, y) != 0 {
+                pixels.push((x, y));
             }
         }
-        self.out.show();
     }
+    pixels
+}
+
+#[test]
+fn check_scroll_right() {
+    for &hires in &[false, true] {
+        let mut graphics = setup_graphics(hires);
+        let right = graphics.width() - 1;
+        draw_pixels(&mut graphics, &[(0, 0), (1, 5), (right - 2, 3), (right, 7)]);
+        graphics.scroll_right(4);
+        assert_eq!(set_pixels(&graphics), vec![(4, 0), (5, 5)]);
+    }
+}
+
+#[test]
+fn check_scroll_left() {
+    for &hires in &[false, true] {
+        let mut graphics = setup_graphics(hires);
+        let right = graphics.width() - 1;
+        draw_pixels(&mut graphics, &[(2, 0), (4, 1), (right, 6)]);
+        graphics.scroll_left(4);
+        assert_eq!(set_pixels(&graphics), vec![(0, 1), (right - 4, 6)]);
+    }
+}
+
+#[test]
+fn check_scroll_down() {
+    for &hires in &[false, true] {
+        let mut graphics = setup_graphics(hires);
+        let bottom = graphics.height() - 1;
+        draw_pixels(&mut graphics, &[(3, 0), (9, 2), (1, bottom - 1), (5, bottom)]);
+        graphics.scroll_down(2);
+        assert_eq!(set_pixels(&graphics), vec![(3, 2), (9, 4)]);
+    }
+}
+
+#[test]
+fn check_scroll_up() {
+    for &hires in &[false, true] {
+        let mut graphics = setup_graphics(hires);
+        let bottom = graphics.height() - 1;
+        draw_pixels(&mut graphics, &[(3, 0), (9, 2), (5, bottom)]);
+        graphics.scroll_up(2);
+        assert_eq!(set_pixels(&graphics), vec![(9, 0), (5, bottom - 2)]);
+    }
+}
+
+#[test]
+fn check_doubled_lores_pixels() {
+    let mut graphics = setup_graphics(false);
+    graphics.set_doubled_lores(true);
+    assert_eq!(graphics.width(), 64);
+    graphics.draw_line(1, 1, 0x80, 8, true, 0);
+    assert_eq!(graphics.pixel(1, 1), 1);
+
+    /* Switching to hires shows the 2x2 block */
+    graphics.set_mode(true);
+    assert_eq!(set_pixels(&graphics), vec![(2, 2), (3, 2), (2, 3), (3, 3)]);
+    assert!(graphics.draw_line(3, 3, 0x80, 8, true, 0));
+    graphics.set_mode(false);
+    assert_eq!(graphics.pixel(1, 1), 1);
+}
+
+#[test]
+fn check_doubled_lores_scrolls_framebuffer_pixels() {
+    let mut graphics = setup_graphics(false);
+    graphics.set_doubled_lores(true);
+    graphics.draw_pix(0, 0, 1);
+    graphics.scroll_right(1);
+    graphics.set_mode(true);
+    assert_eq!(set_pixels(&graphics), vec![(1, 0), (2, 0), (1, 1), (2, 1)]);
+}
+
+#[test]
+fn check_only_changed_pixels_drawn() {
+    let screen = CountingScreen::new();
+    let mut graphics = Graphics::new(Box::new(screen.clone()));
+    graphics.show();
+    assert_eq!(screen.shown.get(), 1);
+
+    /* Redrawing the same sprite twice in a frame changes nothing */
+    graphics.draw_line(3, 4, 0xF0, 8, true, 0);
+    graphics.draw_line(3, 4, 0xF0, 8, true, 0);
+    graphics.show();
+    assert_eq!(screen.pixels_drawn.get(), 0);
+
+    graphics.draw_line(3, 4, 0xF0, 8, true, 0);
+    graphics.show();
+    assert_eq!(screen.pixels_drawn.get(), 4);
+    assert_eq!(screen.shown.get(), 2);
+
+    /* Nothing is presented when the framebuffer is untouched */
+    graphics.show();
+    assert_eq!(screen.shown.get(), 2);
+
+    graphics.clear_screen();
+    graphics.show();
+    assert_eq!(screen.pixels_drawn.get(), 8);
+}
+
+#[test]
+fn check_mode_switch_redraws() {
+    let screen = CountingScreen::new();
+    let mut graphics = Graphics::new(Box::new(screen.clone()));
+    graphics.draw_line(0, 0, 0x80, 8, true, 0);
+    graphics.show();
+    assert_eq!(screen.pixels_drawn.get(), 1);
+
+    graphics.set_mode(true);
+    graphics.show();
+    assert_eq!(screen.pixels_drawn.get(), 2);
+    assert_eq!(screen.shown.get(), 2);
+}
+
+#[test]
+fn check_line_wraps_and_clips_at_right_edge() {
+    for &hires in &[false, true] {
+        let mut graphics = setup_graphics(hires);
+        let right = graphics.width() - 1;
+        /* 16 pixel line with its first and last pixels set */
+        graphics.draw_line(right - 1, 0, 0x8001, 16, false, 0);
+        assert_eq!(set_pixels(&graphics), vec![(13, 0), (right - 1, 0)]);
+
+        graphics.draw_line(right - 1, 1, 0x8001, 16, true, 0);
+        assert_eq!(set_pixels(&graphics), vec![(13, 0), (right - 1, 0), (right - 1, 1)]);
+        assert!(graphics.draw_line(right - 1, 0, 0xC000, 16, true, 0));
+    }
+}
+
+#[test]
+fn check_version_increases_on_change() {
+    let mut graphics = setup_graphics(false);
+    let mut version = graphics.version();
+    graphics.draw_line(0, 0, 0x80, 8, true, 0);
+    assert!(graphics.version() > version);
+
+    version = graphics.version();
+    graphics.show();
+    assert_eq!(graphics.version(), version);
+    graphics.scroll_down(1);
+    assert!(graphics.version() > version);
+}
+
+#[test]
+fn check_plane_rows() {
+    let mut graphics = setup_graphics(true);
+    graphics.select_planes(3);
+    graphics.draw_pix(1, 2, 2);
+    assert_eq!(graphics.plane_row(0, 2), 0);
+    assert_eq!(graphics.plane_row(1, 2), 1 << 126);
+    assert_eq!(&graphics.row(2)[.. 3], &[0, 2, 0]);
+}
+
+#[test]
+fn check_scroll_past_edge_clears() {
+    let mut graphics = setup_graphics(false);
+    draw_pixels(&mut graphics, &[(0, 0), (63, 31)]);
+    graphics.scroll_down(15);
+    graphics.scroll_down(15);
+    graphics.scroll_down(15);
+    assert!(set_pixels(&graphics).is_empty());
+}
+
 }
@@ -0,0 +1,73 @@
+const OFFSET_BASIS : u64 = 0xCBF29CE484222325;
+const PRIME : u64 = 0x100000001B3;
+
+
+/// 64 bit FNV-1a hash, fed a slice at a time. Used wherever
+/// data is identified or checked across runs, so the values
+/// must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fnv1a {
+    hash: u64
+}
+
+impl Fnv1a {
+
+    pub fn new() -> Fnv1a {
+        Fnv1a { hash: OFFSET_BASIS }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for &b in data {
+            self.hash = (self.hash ^ b as u64).wrapping_mul(PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Fnv1a {
+        Fnv1a::new()
+    }
+}
+
+/// FNV-1a hash of a whole slice.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// 32 bit FNV-1a hash, only used to check version 1 save states.
+pub fn fnv1a_32(data: &[u8]) -> u32 {
+    data.iter().fold(0x811C9DC5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Fnv1a, fnv1a, fnv1a_32};
+
+#[test]
+fn check_fnv1a() {
+    assert_eq!(fnv1a(&[]), 0xCBF29CE484222325);
+    assert_eq!(fnv1a(b"a"), 0xAF63DC4C8601EC8C);
+    assert_eq!(fnv1a(b"foobar"), 0x85944171F73967E8);
+
+    /* Hashing in pieces gives the same result */
+    let mut hasher = Fnv1a::new();
+    hasher.write(b"foo");
+    hasher.write(b"bar");
+    assert_eq!(hasher.finish(), fnv1a(b"foobar"));
+}
+
+#[test]
+fn check_fnv1a_32() {
+    assert_eq!(fnv1a_32(&[]), 0x811C9DC5);
+    assert_eq!(fnv1a_32(b"a"), 0xE40C292C);
+}
+
+}
@@ -0,0 +1,47 @@
+use std::rc::Rc;
+use std::cell::Cell;
+use super::Input;
+
+/// In memory keypad, clones share the same key state so a
+/// handle can be kept to press keys after the original
+/// has been handed to the CPU.
+#[derive(Clone)]
+pub struct MockIO {
+    keys: Rc<Cell<u16>> /* bit n set if key n is held down */
+}
+
+impl MockIO {
+
+    pub fn new() -> MockIO {
+        MockIO { keys: Rc::new(Cell::new(0)) }
+    }
+
+    pub fn set_key(&self, key_index:u8, pressed:bool) {
+        let mask = 1u16 << (key_index & 0xF);
+        let keys = self.keys.get();
+        self.keys.set(if pressed { keys | mask } else { keys & !mask });
+    }
+}
+
+impl Default for MockIO {
+
+    fn default() -> MockIO {
+        MockIO::new()
+    }
+}
+
+impl Input for MockIO {
+
+    /* Returns the lowest key currently held down, there is
+     * nothing to wait on so panics if no key is pressed */
+    fn wait_for_key(&mut self) -> u8 {
+        match (0 .. 16).find(|&k| self.keys.get() & (1 << k) != 0) {
+            Some(k) => k as u8,
+            None => panic!("No key pressed on mock keypad")
+        }
+    }
+
+    fn is_key_pressed(&mut self, key_index:u8) -> bool {
+        self.keys.get() & (1 << (key_index & 0xF)) != 0
+    }
+}
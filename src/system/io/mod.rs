@@ -1,29 +1,48 @@
+#[cfg(feature = "sdl")]
 pub mod sdl_io;
+pub mod mock_io;
+pub mod movie_io;
 
-static DEFAULT_KEYS : [char; 16] = ['z', 'x', 'c', 'v',
+/// Keyboard keys mapped to CHIP keys 0x0 - 0xF.
+pub static DEFAULT_KEYS : [char; 16] = ['z', 'x', 'c', 'v',
                                           'a', 's', 'd', 'f',
                                           'q', 'w', 'e', 'r',
                                           '1', '2', '3', '4'];
 
+/// Source of CHIP keypad input, keys are identified
+/// by their index 0x0 - 0xF on the hex keypad.
+pub trait Input {
+    fn wait_for_key(&mut self) -> u8;
+    fn is_key_pressed(&mut self, key_index:u8) -> bool;
+
+    /// Key for FX0A to store. Blocks on `wait_for_key` by default,
+    /// keypads which can't block, such as one replaying a movie,
+    /// return None and FX0A is executed again.
+    fn poll_key(&mut self) -> Option<u8> {
+        Some(self.wait_for_key())
+    }
+}
+
+/// Keypad of the emulated machine.
 pub struct IO {
-    keyboard: sdl_io::IOImpl,
-    keys : [char; 16]
+    keyboard: Box<dyn Input>
 }
 
 impl IO {
 
-    pub fn new() -> IO {        
-       IO { keyboard: sdl_io::IOImpl::new(DEFAULT_KEYS),
-            keys: DEFAULT_KEYS }
-
-        
+    pub fn new(keyboard: Box<dyn Input>) -> IO {        
+       IO { keyboard }
     }
     
     pub fn wait_for_key(&mut self) -> u8 {
-        self.keyboard.get_key()
+        self.keyboard.wait_for_key()
     }
 
     pub fn is_key_pressed(&mut self, key_index:u8) -> bool {
-        self.keyboard.key_pressed(self.keys[key_index as usize])
+        self.keyboard.is_key_pressed(key_index)
+    }
+
+    pub fn poll_key(&mut self) -> Option<u8> {
+        self.keyboard.poll_key()
     }
 }
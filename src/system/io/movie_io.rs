@@ -0,0 +1,53 @@
+use std::rc::Rc;
+use std::cell::Cell;
+use super::Input;
+
+/// Keypad driven a frame at a time, when recording or replaying a
+/// movie. Clones share the same key state so the recorder or player
+/// can set the keys after the keypad has been handed to the CPU.
+///
+/// It never blocks, FX0A repeats until a frame has a key held.
+#[derive(Clone)]
+pub struct MovieKeypad {
+    keys: Rc<Cell<u16>> /* bit n set if key n is held down */
+}
+
+impl MovieKeypad {
+
+    pub fn new() -> MovieKeypad {
+        MovieKeypad { keys: Rc::new(Cell::new(0)) }
+    }
+
+    /// Keys held down, bit n set for key n.
+    pub fn keys(&self) -> u16 {
+        self.keys.get()
+    }
+
+    pub fn set_keys(&self, keys:u16) {
+        self.keys.set(keys);
+    }
+}
+
+impl Default for MovieKeypad {
+    fn default() -> MovieKeypad {
+        MovieKeypad::new()
+    }
+}
+
+impl Input for MovieKeypad {
+
+    /* The CPU only calls poll_key, there is nothing to wait on
+     * so this returns key 0 if no key is held */
+    fn wait_for_key(&mut self) -> u8 {
+        self.poll_key().unwrap_or(0)
+    }
+
+    fn is_key_pressed(&mut self, key_index:u8) -> bool {
+        self.keys.get() & (1 << (key_index & 0xF)) != 0
+    }
+
+    /* Lowest key held down */
+    fn poll_key(&mut self) -> Option<u8> {
+        (0 .. 16).find(|&k| self.keys.get() & (1 << k) != 0).map(|k| k as u8)
+    }
+}
@@ -1,13 +1,30 @@
 extern crate sdl;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use super::Input;
+
+
+/* Set by Escape or closing the window, wherever the keyboard was read */
+static QUIT_REQUESTED : AtomicBool = AtomicBool::new(false);
+
+fn request_quit() {
+    QUIT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether Escape was pressed or the window closed, the
+/// emulator should stop running the program and exit.
+pub fn quit_requested() -> bool {
+    QUIT_REQUESTED.load(Ordering::SeqCst)
+}
+
 pub struct IOImpl { 
     key_set: [char; 16]
 }
 
 fn index(keyset:[char; 16], key:char) -> Option<u8> {
-    for i in keyset.iter() {
+    for (idx, i) in keyset.iter().enumerate() {
         if *i == key {
-            return Some(*i as u8);
+            return Some(idx as u8);
         }      
     }   
     None
@@ -17,23 +34,28 @@ fn index(keyset:[char; 16], key:char) -> Option<u8> {
 impl IOImpl {
     
     pub fn new(key_set: [char; 16]) -> IOImpl {
-        IOImpl {  key_set: key_set}
+        IOImpl { key_set }
     }
 
 
 
+    /// Block until a keypad key is pressed, returns key 0
+    /// without waiting once quitting has been requested.
     pub fn get_key(&mut self) -> u8 {
         'key_loop : loop {
+            if quit_requested() {
+                return 0;
+            }
             match sdl::event::wait_event() {
+            sdl::event::Event::Quit => request_quit(),
             sdl::event::Event::Key(k, _, _, _) =>  {
                 if k as usize == sdl::event::Key::Escape as usize {
-                    panic!("Exited program");
+                    request_quit();
+                    continue 'key_loop;
+                }
+                if let Some(index) = index(self.key_set, (k as u8) as char) {
+                    return index;
                 }
-                else {println!("pressed {}", k as usize);}
-                match index(self.key_set, (k as u8) as char) {
-                    Some(index) => { return index;},
-                    None => {}
-                }; 
             },
 
             _ => {}
@@ -43,11 +65,11 @@ impl IOImpl {
     }
 
 
-    fn is_pressed(keyboard_state : Vec<(sdl::event::Key, bool)>,  key: char) -> bool {
+    fn is_pressed(keyboard_state : &[(sdl::event::Key, bool)],  key: char) -> bool {
         for i in keyboard_state.iter() {
            let (k, state) = *i;
            if (k as u8) == sdl::event::Key::Escape as u8 && state {
-                panic!("Exited Program");
+                request_quit();
             }
             match *i { 
                 (k, state) if (k as u8) == (key as u8) => return state,
@@ -56,9 +78,105 @@ impl IOImpl {
         false
     }
 
+    /// State of the whole keypad, bit n set if key n is held down.
+    /// Sampled once per frame when recording a movie.
+    pub fn keypad_state(&mut self) -> u16 {
+        sdl::event::pump_events();
+        let state = sdl::event::get_key_state();
+        (0 .. 16).filter(|&k| IOImpl::is_pressed(&state, self.key_set[k]))
+                 .fold(0, |keys, k| keys | (1 << k))
+    }
+
     pub fn key_pressed(&mut self, key:char) -> bool {
             sdl::event::pump_events(); /* Update current keyboard state */
             let state = sdl::event::get_key_state();
-            IOImpl::is_pressed(state, key)
+            IOImpl::is_pressed(&state, key)
+    }
+}
+
+
+impl Input for IOImpl {
+
+    fn wait_for_key(&mut self) -> u8 {
+        self.get_key()
+    }
+
+    fn is_key_pressed(&mut self, key_index:u8) -> bool {
+        let key = self.key_set[(key_index & 0xF) as usize];
+        self.key_pressed(key)
+    }
+}
+
+
+/// Number of save state slots selectable with the function keys.
+pub const SLOT_COUNT : usize = 8;
+
+static SLOT_KEYS : [sdl::event::Key; SLOT_COUNT] =
+    [sdl::event::Key::F1, sdl::event::Key::F2, sdl::event::Key::F3, sdl::event::Key::F4,
+     sdl::event::Key::F5, sdl::event::Key::F6, sdl::event::Key::F7, sdl::event::Key::F8];
+
+/// Save state hotkey, with the slot numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotHotkey {
+    /// Shift + F1 - F8
+    Save(usize),
+    /// F1 - F8
+    Load(usize)
+}
+
+/// Watches the function keys for save state hotkeys, each
+/// press is reported once however long the key is held.
+/// Backspace is held to rewind.
+pub struct Hotkeys {
+    held: [bool; SLOT_COUNT],
+    rewind_held: bool
+}
+
+impl Default for Hotkeys {
+    fn default() -> Hotkeys {
+        Hotkeys::new()
+    }
+}
+
+impl Hotkeys {
+
+    pub fn new() -> Hotkeys {
+        Hotkeys { held: [false; SLOT_COUNT], rewind_held: false }
+    }
+
+    /// Whether the rewind key was held at the last `poll`.
+    pub fn rewind_held(&self) -> bool {
+        self.rewind_held
+    }
+
+    /// Check for a newly pressed hotkey, should be called once per frame.
+    /// Escape or closing the window requests quitting, see `quit_requested`.
+    pub fn poll(&mut self) -> Option<SlotHotkey> {
+        loop {
+            match sdl::event::poll_event() {
+                sdl::event::Event::None => break,
+                sdl::event::Event::Quit => request_quit(),
+                _ => {}
+            }
+        }
+        sdl::event::pump_events();
+        let state = sdl::event::get_key_state();
+        let pressed = |key: sdl::event::Key| state.iter()
+            .any(|&(k, down)| down && k as usize == key as usize);
+        let shift = pressed(sdl::event::Key::LShift) || pressed(sdl::event::Key::RShift);
+        self.rewind_held = pressed(sdl::event::Key::Backspace);
+        if pressed(sdl::event::Key::Escape) {
+            request_quit();
+        }
+
+        let mut hotkey = None;
+        for (slot, key) in SLOT_KEYS.iter().enumerate() {
+            let down = pressed(*key);
+            if down && !self.held[slot] && hotkey.is_none() {
+                hotkey = Some(if shift {SlotHotkey::Save(slot + 1)} else {SlotHotkey::Load(slot + 1)});
+            }
+            self.held[slot] = down;
+        }
+        hotkey
     }
 }
@@ -2,33 +2,90 @@ extern crate rand;
 
 pub mod graphics;
 pub mod io;
+pub mod audio;
+pub mod error;
+pub mod hash;
+pub mod scheduler;
+pub mod quirks;
+pub mod platform;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+pub mod movie;
+#[cfg(test)]
+mod test_util;
+
+pub use self::error::CpuError;
+pub use self::quirks::Quirks;
+pub use self::platform::{Platform, Profile};
+pub use self::savestate::SaveStateError;
+use self::platform::Font;
 /* CPU, Graphics and Memory core */
 
 
-const MAX_RAM : u16 = 4096;
 const FLAG : usize = 15;
 const CHIP_MODE : bool = false;
 const SCHIP_MODE : bool = true;
+const STACK_SIZE : usize = 16;
+
+/// Rate in Hz at which the delay and sound timers count down.
+pub const TIMER_FREQUENCY : u64 = 60;
+
+/// Result of successfully stepping the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// An instruction was executed, execution can continue.
+    Continue,
+    /// A sprite was drawn with the display wait quirk enabled, no
+    /// further instructions will execute until the next frame.
+    WaitingForVBlank,
+    /// The program has exited, no further instructions will execute.
+    Halted
+}
 
+/// What the CPU does when it encounters an instruction it can't decode.
+pub enum InvalidOpcodePolicy {
+    /// Stop execution, reporting `CpuError::UnknownOpcode`.
+    Halt,
+    /// Treat the instruction as a no-op and continue.
+    Skip,
+    /// Call the handler with the opcode and its address, then continue.
+    Trap(Box<dyn FnMut(u16, u16)>)
+}
+
+/// The CHIP 8 virtual machine: registers, memory, timers,
+/// framebuffer and keypad.
 pub struct CPU {
      registers : [u8; 16], /* 16 8 bit general purpose registers */
-     mem : [u8; MAX_RAM as usize], /* 4096bytes of memory */
+     mem : Vec<u8>, /* 4096 bytes of memory, 64KiB on XO-CHIP */
      index_reg : u16, /* 16 bit index register */
      pc: u16, /* Program counter */
-     sp: usize, /* Stack Pointer */
-     stack : [u16; 16], /* 16 stack frames */
+     sp: usize, /* Stack Pointer, number of frames on the stack */
+     stack : [u16; STACK_SIZE], /* 16 stack frames */
+     stack_depth: usize, /* Frames usable on the current platform */
      sound_timer : u8, 
      delay_timer : u8,
-     hp_48_flags: [u8; 8], /*SCHIP */
+     audio_pattern: [u8; audio::PATTERN_SIZE], /* XO-CHIP 1 bit sample pattern */
+     pitch: u8, /* XO-CHIP pattern playback pitch */
+     hp_48_flags: [u8; 16], /*SCHIP */
+     hp_flag_count: usize, /* 8 on SCHIP, 16 on XO-CHIP */
      graphics :graphics::Graphics,
      io :io::IO,
      halt:bool,
-     mode: bool
+     mode: bool,
+     quirks: Quirks,
+     schip_instructions: bool,
+     scroll_up_instruction: bool,
+     xo_chip_instructions: bool,
+     font: Font,
+     waiting_for_vblank: bool, /* Set after drawing with the display wait quirk */
+     rng: Box<dyn random::RandomSource>, /* Source of CXNN random numbers */
+     invalid_opcode_policy: InvalidOpcodePolicy
 
 }
 
 
-static SPRITE_SET: [u8; (80 + 160)] =   
+static SPRITE_SET: [u8; 80 + 160] =   
    [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -64,25 +121,78 @@ static SPRITE_SET: [u8; (80 + 160)] =
     0x00, 0x7E, 0x22, 0x28, 0x38, 0x28, 0x20, 0x22, 0x7E, 0x00, //E
     0x00, 0x7E, 0x22, 0x28, 0x38, 0x28, 0x20, 0x20, 0x70, 0x00  //F
   ]; 
+
+/* Small font from the COSMAC VIP, differs from the
+ * CHIP-48 font for 1, 4, 7, B and D */
+static VIP_SPRITE_SET: [u8; 80] =
+   [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+  ];
    
 
 #[allow(dead_code)]
 impl CPU {
 
-  pub fn new(mem: Vec<u8>) -> CPU {
+  /// Create a CPU with the supplied memory image and interpreter quirks,
+  /// which renders to the given display and reads keypad state from
+  /// the given input. Other than the quirks the CPU behaves as the
+  /// default platform.
+  pub fn new(mem: Vec<u8>, quirks: Quirks, display: Box<dyn graphics::Display>,
+             input: Box<dyn io::Input>) -> CPU {
+      let profile = Profile { quirks, .. Platform::default().profile() };
+      CPU::with_profile(mem, profile, display, input)
+  }
+
+  /// Create a CPU which behaves as the given platform.
+  pub fn with_platform(mem: Vec<u8>, platform: Platform, display: Box<dyn graphics::Display>,
+                       input: Box<dyn io::Input>) -> CPU {
+      CPU::with_profile(mem, platform.profile(), display, input)
+  }
+
+  /// Create a CPU with the given platform profile.
+  pub fn with_profile(mem: Vec<u8>, profile: Profile, display: Box<dyn graphics::Display>,
+                      input: Box<dyn io::Input>) -> CPU {
         let mut cpu = CPU { registers: [0u8; 16], 
-              mem: [0u8; (MAX_RAM as usize)],
+              mem: vec![0u8; profile.memory_size],
               index_reg: 0,
               pc: 0x200,
-              sp: 1,
-              stack : [0u16; 16],
+              sp: 0,
+              stack : [0u16; STACK_SIZE],
+              stack_depth: if profile.stack_depth < STACK_SIZE 
+                               {profile.stack_depth} else {STACK_SIZE},
               sound_timer: 0,
               delay_timer: 0,
-              hp_48_flags: [0u8; 8],
-              graphics : graphics::Graphics::new(),
-              io : io::IO::new(),
+              audio_pattern: audio::DEFAULT_PATTERN,
+              pitch: audio::DEFAULT_PITCH,
+              hp_48_flags: [0u8; 16],
+              hp_flag_count: if profile.xo_chip_instructions {16} else {8},
+              graphics : graphics::Graphics::new(display),
+              io : io::IO::new(input),
               halt:false,
-              mode:CHIP_MODE 
+              mode:CHIP_MODE,
+              quirks: profile.quirks,
+              schip_instructions: profile.schip_instructions,
+              scroll_up_instruction: profile.scroll_up_instruction,
+              xo_chip_instructions: profile.xo_chip_instructions,
+              font: profile.font,
+              waiting_for_vblank: false,
+              rng: Box::new(random::XorshiftRng::new(rand::random::<u64>())),
+              invalid_opcode_policy: InvalidOpcodePolicy::Halt
        };
         
        
@@ -91,26 +201,96 @@ impl CPU {
            *m = *v;
        }
       
-       /* Load CHIP8 fontset into unused locations 0x0 - 0x50 in memory */ 
-       for (m, v) in cpu.mem.iter_mut().zip(SPRITE_SET.iter()) {
+       /* Load CHIP8 fontset into unused locations 0x0 - 0x50 in memory,
+        * followed by the SCHIP fontset */ 
+       let font : &[u8] = match profile.font {
+           Font::Vip => &VIP_SPRITE_SET,
+           Font::Chip48 => &SPRITE_SET
+       };
+       for (m, v) in cpu.mem.iter_mut().zip(font.iter()) {
             *m = *v;
        }
 
-       return cpu;
+       cpu.graphics.set_doubled_lores(profile.doubled_lores);
+       cpu
     }
 
+   /// Set how instructions which can't be decoded are handled,
+   /// defaults to `InvalidOpcodePolicy::Halt`.
+   pub fn set_invalid_opcode_policy(&mut self, policy: InvalidOpcodePolicy) {
+       self.invalid_opcode_policy = policy;
+   }
+
+   /// Seed the source of CXNN's random numbers so runs are
+   /// reproducible, otherwise it's seeded randomly.
+   pub fn seed_random(&mut self, seed:u64) {
+       self.rng.set_state(seed);
+   }
+
+   /// Replace the source of CXNN's random numbers, such as with one
+   /// replicating a particular interpreter's or scripted for tests.
+   pub fn set_random_source(&mut self, source: Box<dyn random::RandomSource>) {
+       self.rng = source;
+   }
+
+   /// Read the byte at the given memory location.
    pub fn get_mem(&self, loc:u16) -> u8 {
-       self.mem[(loc % MAX_RAM) as usize]
+       self.mem[loc as usize % self.mem.len()]
+   }
+
+   /// Size of memory in bytes.
+   pub fn mem_size(&self) -> usize {
+       self.mem.len()
    }
 
+   /// Read general purpose register V0 - VF.
    pub fn get_reg(&self, reg:u8) -> u8 {
        self.registers[reg as usize]
    }
 
+   /// Current value of the program counter.
    pub fn get_pc(&self) -> u16 {
        self.pc
    }
 
+   /// Current value of the delay timer.
+   pub fn get_delay_timer(&self) -> u8 {
+       self.delay_timer
+   }
+
+   /// Current value of the sound timer, sound plays while non zero.
+   pub fn get_sound_timer(&self) -> u8 {
+       self.sound_timer
+   }
+
+   /// Read only view of the emulated display, for custom
+   /// renderers, screenshots and tests.
+   pub fn framebuffer(&self) -> &graphics::Graphics {
+       &self.graphics
+   }
+
+   /// Current sound output, for rendering with
+   /// an `audio::PatternGenerator`.
+   pub fn sound(&self) -> audio::Sound {
+       audio::Sound { pattern: self.audio_pattern,
+                      pitch: self.pitch,
+                      playing: self.sound_timer > 0 }
+   }
+
+   /// SCHIP HP48 flags stored by FX75, 8 of them on SCHIP and 16 on
+   /// XO-CHIP. On the HP48 these survive between program runs.
+   pub fn hp_48_flags(&self) -> &[u8] {
+       &self.hp_48_flags[.. self.hp_flag_count]
+   }
+
+   /// Restore HP48 flags saved from a previous run, extra flags are ignored.
+   pub fn set_hp_48_flags(&mut self, flags: &[u8]) {
+       for (flag, value) in self.hp_48_flags[.. self.hp_flag_count].iter_mut().zip(flags.iter()) {
+           *flag = *value;
+       }
+   }
+
+   /// Current value of the index register I.
    pub fn get_index_reg(&self) -> u16 {
        self.index_reg
    }
@@ -135,40 +315,85 @@ impl CPU {
     }
 
     /* obtains the current 16 bit opcode from memory */
-    fn get_opcode(&self) -> u16 {
-
-        (((self.mem[self.pc as usize]) as u16) << 8)  |
-        (self.mem[(self.pc + 1) as usize]) as u16
+    fn get_opcode(&self) -> Result<u16, CpuError> {
+        self.read_word(self.pc)
     } 
 
-    /* perform 1 CPU instruction */
-    fn execute(&mut self, opcode:u16) {
+    /* reads the 16 bit big endian word at the given address */
+    fn read_word(&self, addr:u16) -> Result<u16, CpuError> {
+        let addr = addr as usize;
+        self.check_mem_range(addr, 2)?;
+
+        Ok(((self.mem[addr] as u16) << 8) | self.mem[addr + 1] as u16)
+    }
+
+    /* check that "len" bytes of memory starting at "start" lie
+     * within RAM */
+    fn check_mem_range(&self, start:usize, len:usize) -> Result<(), CpuError> {
+        let size = self.mem.len();
+        if start + len > size {
+            Err(CpuError::MemoryOutOfBounds { addr: 
+                if start >= size {start} else {size} })
+        } else {
+            Ok(())
+        }
+    }
+
+    /* wraps an address to the size of memory */
+    fn wrap_addr(&self, addr:usize) -> u16 {
+        (addr % self.mem.len()) as u16
+    }
+
+    /* perform 1 CPU instruction, if the instruction faults
+     * the program counter is restored to point at it */
+    fn execute(&mut self, opcode:u16) -> Result<StepOutcome, CpuError> {
+        let pc = self.pc;
+        match self.execute_instruction(opcode) {
+            Ok(()) => Ok(match (self.halt, self.waiting_for_vblank) { 
+                (true, _) => StepOutcome::Halted, 
+                (false, true) => StepOutcome::WaitingForVBlank,
+                (false, false) => StepOutcome::Continue 
+            }),
+            Err(e) => {
+                self.pc = pc;
+                Err(e)
+            }
+        }
+    }
+
+    fn execute_instruction(&mut self, opcode:u16) -> Result<(), CpuError> {
         
+        let pc = self.pc;
+        let schip = self.schip_instructions;
+        let xo = self.xo_chip_instructions;
+        let scroll_up = self.scroll_up_instruction;
+        /* DXY0 draws 16x16 sprites */
+        let wide_sprites = schip && 
+            (self.mode == SCHIP_MODE || !self.quirks.lores_dxy0_draws_8x16);
         let opcode_v =  CPU::u16_to_hex_vec(opcode);
         self.inc_pc();
 
         match opcode_v {
             (0x0, 0x0 ,0xE, 0x0) => self.clear_screen(),
-            (0x0, 0x0, 0xE, 0xE) => self.ret(), 
+            (0x0, 0x0, 0xE, 0xE) => self.ret().ok_or(CpuError::StackUnderflow { pc })?, 
            
-            (0x0, a, b, c) => {
-                let mode = self.mode;
-                match (mode, (a, b, c)) {
-                    (SCHIP_MODE, (0x0, 0xC, n)) => self.scroll_n_down(n),
-                    (SCHIP_MODE, (0x0, 0xF, 0xB)) => self.scroll_4_right(),
-                    (SCHIP_MODE, (0x0, 0xF, 0xC)) => self.scroll_4_left(),
-                    (SCHIP_MODE, (0x0, 0xF, 0xD)) => self.exit(),
-                    (SCHIP_MODE, (0x0, 0xF, 0xE)) => self.set_chip_mode(),
-                    (CHIP_MODE, (0x0, 0xF, 0xF)) => self.set_super_chip_mode(),
-                     _ => {}, 
-                }
-            },
+            (0x0, 0x0, 0xB, n) if scroll_up => self.scroll_n_up(n),
+            (0x0, 0x0, 0xC, n) if schip => self.scroll_n_down(n),
+            (0x0, 0x0, 0xD, n) if xo => self.scroll_n_up(n),
+            (0x0, 0x0, 0xF, 0xB) if schip => self.scroll_4_right(),
+            (0x0, 0x0, 0xF, 0xC) if schip => self.scroll_4_left(),
+            (0x0, 0x0, 0xF, 0xD) if schip => self.exit(),
+            (0x0, 0x0, 0xF, 0xE) if schip => self.set_chip_mode(),
+            (0x0, 0x0, 0xF, 0xF) if schip => self.set_super_chip_mode(),
 
             (0x1, n1, n2, n3) => self.jump(CPU::to_addr(n1, n2, n3)),
-            (0x2, n1, n2, n3) => self.call(CPU::to_addr(n1, n2, n3)),
+            (0x2, n1, n2, n3) => self.call(CPU::to_addr(n1, n2, n3))
+                                         .ok_or(CpuError::StackOverflow { pc })?,
             (0x3, x, n1, n2) => self.skip_equals_reg_val(x, CPU::to_val(n1, n2)),
             (0x4, x, n1, n2) => self.skip_not_equals_reg_val(x, CPU::to_val(n1, n2)),
             (0x5, x, y, 0x0) => self.skip_equals_regs(x, y),
+            (0x5, x, y, 0x2) if xo => self.store_reg_range(x, y)?,
+            (0x5, x, y, 0x3) if xo => self.load_reg_range(x, y)?,
             (0x6, x, n1, n2) => self.mov_reg_val(x, CPU::to_val(n1, n2)),
             (0x7, x, n1, n2) => self.add_reg_val(x, CPU::to_val(n1, n2)),
             (0x8, x, y, 0x0) => self.mov_regs(x, y),
@@ -177,100 +402,177 @@ impl CPU {
             (0x8, x, y, 0x3) => self.xor_regs(x, y),
             (0x8, x, y, 0x4) => self.add_regs(x, y),
             (0x8, x, y, 0x5) => self.sub_regs(x, y, x),
-            (0x8, x, _, 0x6) => self.shift_right(x),
+            (0x8, x, y, 0x6) => self.shift_right(x, y),
             (0x8, x, y, 0x7) => self.sub_regs(y, x, x),
-            (0x8, x, _, 0xE) => self.shift_left(x),
+            (0x8, x, y, 0xE) => self.shift_left(x, y),
             (0x9, x, y, 0x0) => self.skip_not_equals_regs(x, y),
             (0xA, n1, n2, n3) => self.set_i(CPU::to_addr(n1, n2, n3)),
-            (0xB, n1, n2, n3) => self.jump_val_reg0(CPU::to_addr(n1, n2, n3)),
+            (0xB, n1, n2, n3) => self.jump_offset(CPU::to_addr(n1, n2, n3)),
             (0xC, x, n1, n2) => self.rand(x, CPU::to_val(n1, n2)),
 
-            (0xD, x, y, n) =>  match (self.mode, (x, y, n)) {
-                (SCHIP_MODE, (x, y, 0x0)) => self.draw_extended_sprite(x, y),
-                (_, (x, y, n))       => self.draw_sprite(x, y, n),
-            },
+            (0xD, x, y, 0x0) if wide_sprites => self.draw_extended_sprite(x, y)?,
+            (0xD, x, y, n) => self.draw_sprite(x, y, n)?,
 
             (0xE, x, 0x9, 0xE) => self.skip_key_pressed(x),
             (0xE, x, 0xA, 0x1) => self.skip_not_key_pressed(x),
+            (0xF, 0x0, 0x0, 0x0) if xo => self.load_long_index()?,
+            (0xF, n, 0x0, 0x1) if xo => self.select_planes(n),
+            (0xF, 0x0, 0x0, 0x2) if xo => self.load_audio_pattern()?,
+            (0xF, x, 0x3, 0xA) if xo => self.set_pitch(x),
             (0xF, x, 0x0, 0x7) => self.set_reg_delay(x),
             (0xF, x, 0x0, 0xA) => self.wait_for_key(x),
             (0xF, x, 0x1, 0x5) => self.set_delay_reg(x),
             (0xF, x, 0x1, 0x8) => self.set_sound_reg(x),
             (0xF, x, 0x1, 0xE) => self.add_reg_index(x),
             (0xF, x, 0x2, 0x9) => self.load_sprite(x),
-            (0xF, x, 0x3, 0x3) => self.binary_decimal(x),
-            (0xF, x, 0x5, 0x5) => self.store_regs(x),
-            (0xF, x, 0x6, 0x5) => self.load_regs(x),
-
-            (0xF, a, b, c) =>  match (self.mode, (a, b, c)) {
-                (SCHIP_MODE, (x, 0x3, 0x0)) => self.load_extended_sprite(x),
-                (SCHIP_MODE, (x, 0x7, 0x5)) => self.store_hp_regs(x),
-                (SCHIP_MODE, (x, 0x8, 0x5)) => self.load_hp_regs(x),
-                _ => panic!("Unknown opcode {:x}", opcode)
-            },
-
-            _ => panic!("Unknown opcode {:x}",opcode)
+            (0xF, x, 0x3, 0x3) => self.binary_decimal(x)?,
+            (0xF, x, 0x5, 0x5) => self.store_regs(x)?,
+            (0xF, x, 0x6, 0x5) => self.load_regs(x)?,
+            (0xF, x, 0x3, 0x0) if schip => self.load_extended_sprite(x),
+            (0xF, x, 0x7, 0x5) if schip => self.store_hp_regs(x),
+            (0xF, x, 0x8, 0x5) if schip => self.load_hp_regs(x),
+
+            _ => self.invalid_opcode(pc, opcode)?
         }
 
-        if self.delay_timer > 0 { self.delay_timer -= 1;}
-        if self.sound_timer > 0 { self.sound_timer -= 1;}
+        Ok(())
     }
 
+    /* handle an instruction which couldn't be decoded
+     * according to the invalid opcode policy */
+    fn invalid_opcode(&mut self, pc:u16, opcode:u16) -> Result<(), CpuError> {
+        match self.invalid_opcode_policy {
+            InvalidOpcodePolicy::Halt => 
+                Err(CpuError::UnknownOpcode { pc, opcode }),
+            InvalidOpcodePolicy::Skip => Ok(()),
+            InvalidOpcodePolicy::Trap(ref mut handler) => {
+                handler(opcode, pc);
+                Ok(())
+            }
+        }
+    }
+
+    /// Fetch and execute the instruction at the program counter.
+    ///
+    /// # Panics
+    /// If the instruction faults, see `try_perform_cycle` for
+    /// a non panicking version.
     pub fn perform_cycle(&mut self) {
-        let opcode = self.get_opcode();
-        self.execute(opcode);
+        if let Err(e) = self.try_perform_cycle() {
+            panic!("{}", e);
+        }
     }
 
+    /// Execute the given opcode as if it were at the program counter.
+    ///
+    /// # Panics
+    /// If the instruction faults, see `try_interpret` for
+    /// a non panicking version.
     pub fn interpret(&mut self, opcode:u16) {
-        self.execute(opcode);
+        if let Err(e) = self.try_interpret(opcode) {
+            panic!("{}", e);
+        }
     }
 
+    /// Fetch and execute the instruction at the program counter,
+    /// returning an error instead of panicking if it faults. Once
+    /// the program has exited no further instructions are executed.
+    pub fn try_perform_cycle(&mut self) -> Result<StepOutcome, CpuError> {
+        if self.halt {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.waiting_for_vblank {
+            return Ok(StepOutcome::WaitingForVBlank);
+        }
+        let opcode = self.get_opcode()?;
+        self.execute(opcode)
+    }
+
+    /// Execute the given opcode as if it were at the program counter,
+    /// returning an error instead of panicking if it faults.
+    pub fn try_interpret(&mut self, opcode:u16) -> Result<StepOutcome, CpuError> {
+        self.execute(opcode)
+    }
+
+    /// Count down the delay and sound timers by one, should be
+    /// called `TIMER_FREQUENCY` times a second independently of
+    /// how many instructions are executed. This also marks the
+    /// start of a new frame for the display wait quirk.
+    pub fn tick_timers(&mut self) {
+        self.waiting_for_vblank = false;
+        if self.delay_timer > 0 { self.delay_timer -= 1;}
+        if self.sound_timer > 0 { self.sound_timer -= 1;}
+    }
+
+    /// Render the current framebuffer to the display, instructions
+    /// only update the framebuffer so this should be called once
+    /// per frame.
+    pub fn present(&mut self) {
+        self.graphics.show();
+    }
+
+    /// Whether the program has exited with 00FD.
     pub fn is_finished(&self) -> bool {
         self.halt
     }
 
     
     /* pop an item from the top of the stack,
-     * decrements the stack pointer after popping */
-    fn pop(&mut self) -> u16 {
-        let item = self.stack[self.sp];
+     * decrements the stack pointer before popping */
+    fn pop(&mut self) -> Option<u16> {
+        if self.sp == 0 {
+            return None;
+        }
         self.sp -= 1;
-        return item;
-         
+        Some(self.stack[self.sp])
     }
     
     /* push an item on to the stack,
-     * increments the stack pointer before
+     * increments the stack pointer after
      * pushing */
-    fn push(&mut self, val:u16) {
-        self.sp += 1;
+    fn push(&mut self, val:u16) -> Option<()> {
+        if self.sp == self.stack_depth {
+            return None;
+        }
         self.stack[self.sp] = val;
+        self.sp += 1;
+        Some(())
     }
 
     /* Increments the program counter */
     fn inc_pc(&mut self) {
-        self.pc += 2;
-        self.pc %= MAX_RAM;
+        self.pc = self.wrap_addr(self.pc as usize + 2);
+    }
+
+    /* Skips the next instruction, on XO-CHIP this
+     * includes both words of a long F000 NNNN load */
+    fn skip(&mut self) {
+        let long_load = self.xo_chip_instructions && self.get_opcode() == Ok(0xF000);
+        self.inc_pc();
+        if long_load {
+            self.inc_pc();
+        }
     }
 
     /* Store the current program counter
      * on the stack and jump to the supplied address */
-    fn call(&mut self, addr:u16) {
+    fn call(&mut self, addr:u16) -> Option<()> {
         let pc = self.pc;
-        self.push(pc);
-        self.pc = addr % MAX_RAM;
+        self.push(pc)?;
+        self.pc = self.wrap_addr(addr as usize);
+        Some(())
     }
 
     /* Clear the display */
     fn clear_screen(&mut self) {
         self.graphics.clear_screen();
-        self.graphics.show();
     }
 
     /* Return from a subroutine by
      * popping the return address from the stack */
-    fn ret(&mut self) {
-        self.pc = self.pop();
+    fn ret(&mut self) -> Option<()> {
+        self.pc = self.pop()?;
+        Some(())
     }
 
     /* Set the program counter to the supplied address */
@@ -282,7 +584,7 @@ impl CPU {
      * supplied value */
     fn skip_equals_reg_val(&mut self, reg:u8, val:u8) {
         if self.registers[reg as usize] == val {
-            self.inc_pc();
+            self.skip();
         }
                 
     }
@@ -290,7 +592,7 @@ impl CPU {
      * to supplied value */
     fn skip_not_equals_reg_val(&mut self, reg:u8, val:u8) {
         if self.registers[reg as usize] != val {
-            self.inc_pc();
+            self.skip();
         }
     }
 
@@ -298,14 +600,14 @@ impl CPU {
     fn skip_equals_regs(&mut self, reg1:u8, reg2:u8) {
         
         if self.registers[reg1 as usize] == self.registers[reg2 as usize] {
-               self.inc_pc();
+               self.skip();
         }
     }
 
     /* Skip next instruction if both register values are not equal */
     fn skip_not_equals_regs(&mut self, reg1:u8, reg2:u8) {
         if self.registers[reg1 as usize] != self.registers[reg2 as usize] {
-               self.inc_pc();
+               self.skip();
            }
     }
 
@@ -329,18 +631,29 @@ impl CPU {
      * registers, store the result in the first register*/
     fn or_regs(&mut self, reg1:u8, reg2:u8) {
        self.registers[reg1 as usize] |= self.registers[reg2 as usize];
+       self.logic_reset_flag();
     }
 
     /* performa binary and on the values of the first and second
      * registers, store the result in the first register */
     fn and_regs(&mut self, reg1:u8, reg2:u8) {
         self.registers[reg1 as usize] &= self.registers[reg2 as usize];
+        self.logic_reset_flag();
     }
 
     /* perform binary xor on the values of the first and second
      * registers, store the result in the first register */
     fn xor_regs(&mut self, reg1:u8, reg2:u8) {
         self.registers[reg1 as usize] ^= self.registers[reg2 as usize];
+        self.logic_reset_flag();
+    }
+
+    /* on interpreters with the VF reset quirk logical
+     * operations clear the flag register */
+    fn logic_reset_flag(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[FLAG] = 0;
+        }
     }
 
     /* Add two regs, if overflow set flag register otherwise
@@ -368,19 +681,30 @@ impl CPU {
     }
 
 
-    /* Shift register left by 1, set flag register to most significant bit
-     * before shifting */
-    fn shift_left(&mut self, reg:u8) {
-        self.registers[FLAG] = (self.registers[reg as usize] & 0x80) >> 7;
-        self.registers[reg as usize] <<= 1;
+    /* the register shifted by 8XY6/8XYE, depending on the
+     * interpreter this is either VX or VY */
+    fn shift_source(&self, reg1:u8, reg2:u8) -> u8 {
+        match self.quirks.shift_uses_vy {
+            true => self.registers[reg2 as usize],
+            false => self.registers[reg1 as usize]
+        }
+    }
+
+    /* Shift source register left by 1 and store in the first register, 
+     * set flag register to most significant bit before shifting */
+    fn shift_left(&mut self, reg1:u8, reg2:u8) {
+        let val = self.shift_source(reg1, reg2);
+        self.registers[reg1 as usize] = val << 1;
+        self.registers[FLAG] = (val & 0x80) >> 7;
     }
 
 
-    /* Shift register right by 1, set flag register to least significant bit
-     * before shifting */
-    fn shift_right(&mut self, reg:u8) {
-        self.registers[FLAG] = self.registers[reg as usize] & 0x1;
-        self.registers[reg as usize] >>= 1;
+    /* Shift source register right by 1 and store in the first register, 
+     * set flag register to least significant bit before shifting */
+    fn shift_right(&mut self, reg1:u8, reg2:u8) {
+        let val = self.shift_source(reg1, reg2);
+        self.registers[reg1 as usize] = val >> 1;
+        self.registers[FLAG] = val & 0x1;
     }
 
     /* Set index register to supplied address */
@@ -388,14 +712,20 @@ impl CPU {
         self.index_reg = addr;
     }
 
-    /* jump to the supplied address + value in register 0 */
-    fn jump_val_reg0(&mut self, addr:u16) {
-        self.pc = (self.registers[0] as u16 + addr) % MAX_RAM;
+    /* jump to the supplied address + value in register 0, or 
+     * with the jump quirk the register given by the top digit 
+     * of the address */
+    fn jump_offset(&mut self, addr:u16) {
+        let reg = match self.quirks.jump_uses_vx {
+            true => (addr >> 8) as usize,
+            false => 0
+        };
+        self.pc = self.wrap_addr(self.registers[reg] as usize + addr as usize);
     }
 
     /* set register to supplied value and a random integer between 0 and 255 */
     fn rand(&mut self, reg:u8, val:u8) {
-        self.registers[reg as usize] = val & rand::random::<u8>();
+        self.registers[reg as usize] = val & self.rng.next_u8();
     }
 
     /* Set the delay timer to the value in the register */
@@ -414,96 +744,150 @@ impl CPU {
     }
 
     /* add the value in the register to value in the index register and store
-     * the result in the index register. If this operation causes overflow
-     * set the flag register, otherwise unset it */
+     * the result in the index register. If this operation goes past the
+     * end of memory set the flag register, otherwise unset it */
     fn add_reg_index(&mut self, reg:u8) {
-        let reg = reg as usize;
+        let addr = self.index_reg as usize + self.registers[reg as usize] as usize;
         self.registers[FLAG] = 
-            match (0xFFF - (self.registers[reg] as u16)) < self.index_reg {
+            match addr > self.mem.len() - 1 {
                 true => 1,
                 false => 0
             }; 
-        self.index_reg += self.registers[reg] as u16;
-        self.index_reg %= MAX_RAM;
+        self.index_reg = self.wrap_addr(addr);
     }
 
     /* store the values from register 0 up to and including
      * the supplied register number starting from memory location
      * pointed to by the index register */
-    fn store_regs(&mut self, max_reg:u8) {
-        let regs = (&self.registers[.. max_reg as usize + 1]).iter();
-        let store = (&mut self.mem[self.index_reg as usize ..]).iter_mut();
+    fn store_regs(&mut self, max_reg:u8) -> Result<(), CpuError> {
+        self.check_mem_range(self.index_reg as usize, max_reg as usize + 1)?;
+        let regs = self.registers[.. max_reg as usize + 1].iter();
+        let store = self.mem[self.index_reg as usize ..].iter_mut();
         /* itterate through both memory and registers*/
         for (mem, reg) in store.zip(regs) {
             *mem = *reg;
         }
-
+        self.load_store_increment(max_reg);
+        Ok(())
     }
 
 
     /* load values into register 0 up to and including
      * the supplied register number starting from memory location
      * pointed to by the index register */
-    fn load_regs(&mut self, max_reg:u8) {
-        let regs = (&mut self.registers[.. max_reg as usize + 1]).iter_mut();
-        let store = (&self.mem[self.index_reg as usize ..]).iter();
+    fn load_regs(&mut self, max_reg:u8) -> Result<(), CpuError> {
+        self.check_mem_range(self.index_reg as usize, max_reg as usize + 1)?;
+        let regs = self.registers[.. max_reg as usize + 1].iter_mut();
+        let store = self.mem[self.index_reg as usize ..].iter();
         /* itterate through both memory and registers */
         for (mem, reg) in store.zip(regs) {
             *reg = *mem;
         }
+        self.load_store_increment(max_reg);
+        Ok(())
    }
 
+    /* on interpreters with the load/store quirk I is left
+     * pointing after the last register stored/loaded */
+    fn load_store_increment(&mut self, max_reg:u8) {
+        if self.quirks.load_store_increments_i {
+            self.index_reg = self.wrap_addr(self.index_reg as usize + max_reg as usize + 1);
+        }
+    }
+
 
     /*stores the Binary-coded decimal representation of VX, with the
      * most significant of three digits at the address in I, the middle digit
      * at I + 1, and the LSD at I + 2.*/
-    fn binary_decimal(&mut self, reg:u8) {
+    fn binary_decimal(&mut self, reg:u8) -> Result<(), CpuError> {
+        let i = self.index_reg as usize;
+        self.check_mem_range(i, 3)?;
         let val = self.registers[reg as usize];
-        self.mem[self.index_reg as usize] = val/100;
-        self.mem[(self.index_reg + 1) as usize] = (val % 100)/10;
-        self.mem[(self.index_reg + 2) as usize] = (val % 100)%10;
- 
+        self.mem[i] = val/100;
+        self.mem[i + 1] = (val % 100)/10;
+        self.mem[i + 2] = (val % 100)%10;
+        Ok(())
     }
     
     /* Draw sprite starting at x,y which is n lines
      * of 8 pixels stored starting at memory location 
-     * of the contents of register I*/
-    fn draw_sprite(&mut self, x:u8, y:u8, line_count:u8) {
-       
-        self.registers[FLAG] = 0;
-        let n = if line_count == 0 {16} else {line_count};
-        for i in 0 .. n {
-
-            let line : u8 = self.mem[(self.index_reg + (i as u16)) as usize];
-
-            if self.graphics.draw_line(
-                    self.registers[x as usize], 
-                    self.registers[y as usize] + i,  
-                    line as usize, 8) {
+     * of the contents of register I. SCHIP draws 16 lines
+     * for DXY0 in low resolution, CHIP 8 draws nothing */
+    fn draw_sprite(&mut self, x:u8, y:u8, line_count:u8) -> Result<(), CpuError> {
+        let n = if line_count == 0 && self.schip_instructions {16} else {line_count};
+        self.draw(x, y, n as usize, 8)
+    }
 
-                self.registers[FLAG] = 1;
+    /* Draw a sprite with the given number of rows each 8 or 16 pixels
+     * wide, the sprite data for each selected plane is stored one after
+     * another starting at the memory location in the index register.
+     * VF is set to 1 if any set pixel was unset, or with the SCHIP 1.1
+     * quirk in hires mode the number of rows which collided or were 
+     * clipped off the bottom of the screen */
+    fn draw(&mut self, x:u8, y:u8, rows:usize, width:usize) -> Result<(), CpuError> {
+        let row_bytes = width / 8;
+        let plane_size = rows * row_bytes;
+        let plane_count = self.graphics.selected_plane_count();
+        self.check_mem_range(self.index_reg as usize, plane_size * plane_count)?;
+        let (start_x, start_y) = self.sprite_origin(x, y);
+        let clip = self.quirks.clip_sprites;
+        let mut collided = 0u32; /* Bit mask of rows which collided */
+
+        for (i, plane) in self.graphics.selected_planes().enumerate() {
+            let data = self.index_reg as usize + i * plane_size;
+            for row in 0 .. rows {
+                let line = self.mem[data + row * row_bytes .. data + (row + 1) * row_bytes]
+                    .iter()
+                    .fold(0usize, |line, &byte| (line << 8) | byte as usize);
+
+                if self.graphics.draw_line(start_x, start_y + row,
+                        line, width, clip, plane) {
+                    collided |= 1 << row;
+                }
             }
         }
 
-        self.graphics.show();       
+        self.registers[FLAG] = if self.quirks.vf_counts_rows && self.mode == SCHIP_MODE {
+            let height = self.graphics.height();
+            (0 .. rows).filter(|&row| collided & (1 << row) != 0 || 
+                                      (clip && start_y + row >= height))
+                       .count() as u8
+        } else {
+            (collided != 0) as u8
+        };
+
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
+    }
+
+    /* Sprites start at the position given by the supplied registers
+     * wrapped to the current screen size, parts of the sprite which
+     * go off screen are clipped or wrapped depending on quirks */
+    fn sprite_origin(&self, x:u8, y:u8) -> (usize, usize) {
+        (self.registers[x as usize] as usize % self.graphics.width(),
+         self.registers[y as usize] as usize % self.graphics.height())
     }
 
     /* set I reg to sprite number stored in the given register */
     fn load_sprite(&mut self, reg:u8) {
-        self.index_reg = (5 * self.registers[reg as usize]) as u16;
+        self.index_reg = 5 * (self.registers[reg as usize] & 0xF) as u16;
     }
 
     /* Wait for a keypress and set the contents of the
-     * given register to that keypress */
+     * given register to that keypress, if the keypad can't
+     * block the instruction repeats until a key is pressed */
     fn wait_for_key(&mut self, reg:u8) {
-        self.registers[reg as usize] = self.io.wait_for_key();  
+        match self.io.poll_key() {
+            Some(key) => self.registers[reg as usize] = key,
+            None => self.pc = self.pc.wrapping_sub(2)
+        }
     }
     
     /* if key in given register is being pressed then
      * skip the next instruction */
     fn skip_key_pressed(&mut self, reg:u8) {
         if self.io.is_key_pressed(self.registers[reg as usize]) {
-            self.inc_pc();
+            self.skip();
         }
     }   
 
@@ -511,25 +895,38 @@ impl CPU {
      * skip the next instruction */
     fn skip_not_key_pressed(&mut self, reg:u8) {
         if !self.io.is_key_pressed(self.registers[reg as usize]) {
-           self.inc_pc();
+           self.skip();
         }
     
     }
 
     /**** Extended Super Chip Instructions ****/
+
+    /* scroll distance in framebuffer pixels for scrolling n pixels 
+     * of the current resolution, or n hires pixels with the quirk */
+    fn scroll_distance(&self, n:u8) -> u8 {
+        let n = n as usize * self.graphics.framebuffer_scale();
+        (if self.quirks.lores_scroll_halved && self.mode == CHIP_MODE {n / 2} else {n}) as u8
+    }
+
     fn scroll_n_down(&mut self, n:u8) {
+        let n = self.scroll_distance(n);
         self.graphics.scroll_down(n);
-        self.graphics.show();
+    }
+
+    fn scroll_n_up(&mut self, n:u8) {
+        let n = self.scroll_distance(n);
+        self.graphics.scroll_up(n);
     }
 
     fn scroll_4_right(&mut self) {
-        self.graphics.scroll_right(4);
-        self.graphics.show();
+        let n = self.scroll_distance(4);
+        self.graphics.scroll_right(n);
     }
 
     fn scroll_4_left(&mut self) {
-        self.graphics.scroll_left(4);
-        self.graphics.show();
+        let n = self.scroll_distance(4);
+        self.graphics.scroll_left(n);
     }
 
     fn exit(&mut self) {
@@ -539,42 +936,35 @@ impl CPU {
 
     fn set_chip_mode(&mut self)  {
         self.mode = CHIP_MODE;
-        self.graphics.set_mode(self.mode);
+        self.switch_mode();
     }
     
     fn set_super_chip_mode(&mut self) {
         self.mode = SCHIP_MODE;
+        self.switch_mode();
+    }
+
+    fn switch_mode(&mut self) {
         self.graphics.set_mode(self.mode);
+        if self.quirks.mode_switch_clears {
+            self.graphics.clear_all();
+        }
     }
 
 
     /* Draw 16*16 sprite at x,y */
-    fn draw_extended_sprite(&mut self, start_x:u8, start_y:u8) {
-        self.registers[FLAG] = 0;
-
-        for y in 0usize .. 16 {
-            let line = ((self.mem[self.index_reg as usize + (2 * y)] as u16) << 4) 
-                | (self.mem[self.index_reg as usize + (2 * y) + 1] as u16);
-            if self.graphics.draw_line(
-                    self.registers[start_x as usize], 
-                    self.registers[start_y as usize] + y as u8, 
-                    line as usize, 16) 
-                {
-                self.registers[FLAG] = 1;
-            }
-        }
-
-        self.graphics.show();
+    fn draw_extended_sprite(&mut self, start_x:u8, start_y:u8) -> Result<(), CpuError> {
+        self.draw(start_x, start_y, 16, 16)
     }
 
     /* load extended sprite 4x10 pixels */
     fn load_extended_sprite(&mut self, reg:u8) {
-        self.index_reg = (0x50 + (0xA * self.registers[reg as usize])) as u16;
+        self.index_reg = 0x50 + 0xA * (self.registers[reg as usize] & 0xF) as u16;
     }
 
     fn store_hp_regs(&mut self, max_reg:u8) {
-        let regs =  (&self.registers[.. max_reg as usize + 1]).iter();
-        let store = (&mut self.hp_48_flags).iter_mut();
+        let regs = self.registers[.. max_reg as usize + 1].iter();
+        let store = self.hp_48_flags[.. self.hp_flag_count].iter_mut();
         /* itterate through both hp registers and general registers*/
         for (hp_reg, reg) in store.zip(regs) {
             *hp_reg = *reg;
@@ -583,26 +973,92 @@ impl CPU {
     }
 
     fn load_hp_regs(&mut self, max_reg:u8) {
-        let regs = (&mut self.registers[(max_reg as usize + 1) ..]).iter_mut();
-        let store = (&self.hp_48_flags).iter();
+        let regs = self.registers[.. max_reg as usize + 1].iter_mut();
+        let store = self.hp_48_flags[.. self.hp_flag_count].iter();
         /* itterate through both memory and registers */
         for (hp_reg, reg) in store.zip(regs) {
             *reg = *hp_reg;
         }
         
     }
+
+    /**** XO-CHIP Instructions ****/
+
+    /* registers from the first register to the last register inclusive, 
+     * in descending order if the first register is the larger */
+    fn reg_range(first:u8, last:u8) -> Vec<usize> {
+        match first <= last {
+            true => (first as usize .. last as usize + 1).collect(),
+            false => (last as usize .. first as usize + 1).rev().collect()
+        }
+    }
+
+    /* store the given range of registers starting at the memory 
+     * location pointed to by the index register, I is unchanged */
+    fn store_reg_range(&mut self, first:u8, last:u8) -> Result<(), CpuError> {
+        let regs = CPU::reg_range(first, last);
+        let i = self.index_reg as usize;
+        self.check_mem_range(i, regs.len())?;
+        for (offset, reg) in regs.into_iter().enumerate() {
+            self.mem[i + offset] = self.registers[reg];
+        }
+        Ok(())
+    }
+
+    /* load the given range of registers from memory starting at the
+     * location pointed to by the index register, I is unchanged */
+    fn load_reg_range(&mut self, first:u8, last:u8) -> Result<(), CpuError> {
+        let regs = CPU::reg_range(first, last);
+        let i = self.index_reg as usize;
+        self.check_mem_range(i, regs.len())?;
+        for (offset, reg) in regs.into_iter().enumerate() {
+            self.registers[reg] = self.mem[i + offset];
+        }
+        Ok(())
+    }
+
+    /* select the bitplanes affected by drawing, 
+     * scrolling and clearing the screen */
+    fn select_planes(&mut self, planes:u8) {
+        self.graphics.select_planes(planes);
+    }
+
+    /* load the 16 byte audio pattern buffer from
+     * the memory location pointed to by I */
+    fn load_audio_pattern(&mut self) -> Result<(), CpuError> {
+        let i = self.index_reg as usize;
+        self.check_mem_range(i, audio::PATTERN_SIZE)?;
+        self.audio_pattern.copy_from_slice(&self.mem[i .. i + audio::PATTERN_SIZE]);
+        Ok(())
+    }
+
+    /* set audio pattern playback pitch to the contents of the given register */
+    fn set_pitch(&mut self, reg:u8) {
+        self.pitch = self.registers[reg as usize];
+    }
+
+    /* load I with the 16 bit address following the F000
+     * instruction and skip over it */
+    fn load_long_index(&mut self) -> Result<(), CpuError> {
+        self.index_reg = self.read_word(self.pc)?;
+        self.inc_pc();
+        Ok(())
+    }
 }
 
 
 
 #[cfg(test)]
 mod tests {
-    use super::CPU;
-    use std::iter;
-
-fn setup_blank_cpu() -> CPU {
-    CPU::new(iter::repeat(0u8).take(0x1).collect()) 
-}
+    use super::{CPU, CpuError, StepOutcome, InvalidOpcodePolicy, Quirks, Platform};
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use super::graphics::Resolution;
+    use super::io::mock_io::MockIO;
+    use super::io::movie_io::MovieKeypad;
+    use super::random::RandomSource;
+    use super::test_util::{setup_cpu, setup_cpu_with_input, setup_cpu_with_quirks,
+                           setup_blank_cpu, setup_cpu_with_keypad};
 
 
 #[test]
@@ -679,7 +1135,7 @@ fn check_sub_regs_overflow() {
     cpu.interpret(0x8795); /* sub r9 from r7 store result in r7 */
 
     assert_eq!(cpu.get_reg(0xF), 0x0); /* check overflow */
-    assert_eq!(cpu.get_reg(0x7), 0x12 - 0x20 as u8);
+    assert_eq!(cpu.get_reg(0x7), 0x12u8.wrapping_sub(0x20));
 
 }
 
@@ -713,7 +1169,7 @@ fn check_sub_inverted_regs_overflow() {
     cpu.interpret(0x8CE7);
 
     assert_eq!(cpu.get_reg(0xF), 0x0);
-    assert_eq!(cpu.get_reg(0xC), 0x30 - 0xD3 as u8); 
+    assert_eq!(cpu.get_reg(0xC), 0x30u8.wrapping_sub(0xD3)); 
 }
 
 
@@ -742,6 +1198,23 @@ fn check_index_add_reg_overflow() {
     assert_eq!(cpu.get_index_reg(), (0xFFF + 0x056) % 0x1000);
 }
 
+#[test]
+fn check_index_add_reg_xo_chip_memory() {
+    /* I = 0xFFF, V0 = 1, I += V0, I = 0xFFFF, I += V0 */
+    let mut cpu = setup_cpu(Platform::XoChip, &[0xAF, 0xFF, 0x60, 0x01, 0xF0, 0x1E,
+                                                0xF0, 0x00, 0xFF, 0xFF, 0xF0, 0x1E]);
+    for _ in 0 .. 3 {
+        cpu.perform_cycle();
+    }
+    assert_eq!(cpu.get_reg(0xF), 0x0);
+    assert_eq!(cpu.get_index_reg(), 0x1000);
+
+    cpu.perform_cycle();
+    cpu.perform_cycle();
+    assert_eq!(cpu.get_reg(0xF), 0x1);
+    assert_eq!(cpu.get_index_reg(), 0x0);
+}
+
 
 
 /*** Jump instructions */
@@ -837,8 +1310,8 @@ fn check_no_skip_regs_equal() {
 #[test]
 fn check_skip_regs_not_equal() {  
     let mut cpu = setup_blank_cpu();
-    cpu.interpret(0x6661); /* set reg 6 to 0x66 */
-    cpu.interpret(0xEEE); /* set reg E to 0xEE */
+    cpu.interpret(0x6666); /* set reg 6 to 0x66 */
+    cpu.interpret(0x6EEE); /* set reg E to 0xEE */
     let before_pc = cpu.get_pc();
     cpu.interpret(0x96E0);
     assert_eq!(cpu.get_pc(), before_pc + 4);
@@ -851,9 +1324,9 @@ fn check_skip_regs_not_equal() {
 fn check_no_skip_regs_not_equal() {
     let mut cpu = setup_blank_cpu();
     cpu.interpret(0x6555); /* set reg 5 to 0x55 */
-    cpu.interpret(0x6DDD); /* set reg D to 0xDD */
+    cpu.interpret(0x6D55); /* set reg D to 0x55 */
     let before_pc = cpu.get_pc();
-    cpu.interpret(0x5D0);
+    cpu.interpret(0x95D0);
     assert_eq!(cpu.get_pc(), before_pc + 2);
 
 }
@@ -901,6 +1374,690 @@ fn check_return() {
 }
 
 
+/*** Check instructions EX9E and EXA1 ***/
+
+#[test]
+fn check_skip_key_pressed() {
+    let keypad = MockIO::new();
+    let mut cpu = setup_cpu_with_keypad(keypad.clone());
+    keypad.set_key(0xA, true);
+    cpu.interpret(0x630A); /* set reg 3 to key A */
+    let before_pc = cpu.get_pc();
+    cpu.interpret(0xE39E);
+    assert_eq!(cpu.get_pc(), before_pc + 4);
+    cpu.interpret(0xE3A1);
+    assert_eq!(cpu.get_pc(), before_pc + 6);
+}
+
+#[test]
+fn check_skip_key_not_pressed() {
+    let keypad = MockIO::new();
+    let mut cpu = setup_cpu_with_keypad(keypad.clone());
+    keypad.set_key(0x2, true);
+    cpu.interpret(0x6305); /* set reg 3 to key 5 */
+    let before_pc = cpu.get_pc();
+    cpu.interpret(0xE39E);
+    assert_eq!(cpu.get_pc(), before_pc + 2);
+    cpu.interpret(0xE3A1);
+    assert_eq!(cpu.get_pc(), before_pc + 6);
+}
+
+
+/*** Check instruction FX0A ***/
+
+#[test]
+fn check_wait_for_key() {
+    let keypad = MockIO::new();
+    let mut cpu = setup_cpu_with_keypad(keypad.clone());
+    keypad.set_key(0x7, true);
+    cpu.interpret(0xF40A);
+    assert_eq!(cpu.get_reg(4), 0x7);
+}
+
+#[test]
+fn check_wait_for_key_at_end_of_memory() {
+    /* FX0A in the last word of XO-CHIP memory, the pc wraps to 0 */
+    let mut cpu = setup_cpu_with_input(Platform::XoChip, &[], Box::new(MovieKeypad::new()));
+    cpu.mem[0xFFFE] = 0xF4;
+    cpu.mem[0xFFFF] = 0x0A;
+    cpu.pc = 0xFFFE;
+    cpu.perform_cycle();
+    assert_eq!(cpu.get_pc(), 0xFFFE);
+}
+
+
+/*** Check faults are reported rather than panicking ***/
+
+#[test]
+fn check_unknown_opcode() {
+    let mut cpu = setup_blank_cpu();
+    let before_pc = cpu.get_pc();
+    assert_eq!(cpu.try_interpret(0xF0FF), 
+               Err(CpuError::UnknownOpcode { pc: before_pc, opcode: 0xF0FF }));
+    assert_eq!(cpu.get_pc(), before_pc);
 }
 
+#[test]
+fn check_stack_underflow() {
+    let mut cpu = setup_blank_cpu();
+    assert_eq!(cpu.try_interpret(0x00EE), 
+               Err(CpuError::StackUnderflow { pc: 0x200 }));
+}
 
+#[test]
+fn check_stack_overflow() {
+    let mut cpu = setup_blank_cpu();
+    for _ in 0 .. 16 {
+        assert_eq!(cpu.try_interpret(0x2400), Ok(StepOutcome::Continue));
+    }
+    assert_eq!(cpu.try_interpret(0x2400), 
+               Err(CpuError::StackOverflow { pc: 0x400 }));
+}
+
+#[test]
+fn check_store_regs_out_of_bounds() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0xAFFE); /* set I to 0xFFE */
+    assert_eq!(cpu.try_interpret(0xF155), Ok(StepOutcome::Continue));
+    assert_eq!(cpu.try_interpret(0xF255), 
+               Err(CpuError::MemoryOutOfBounds { addr: 0x1000 }));
+}
+
+#[test]
+fn check_fetch_out_of_bounds() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0x1FFF); /* jump to last byte of memory */
+    assert_eq!(cpu.try_perform_cycle(), 
+               Err(CpuError::MemoryOutOfBounds { addr: 0x1000 }));
+}
+
+#[test]
+fn check_halted_after_exit() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0x00FF); /* enter SCHIP mode */
+    assert_eq!(cpu.try_interpret(0x00FD), Ok(StepOutcome::Halted));
+    assert_eq!(cpu.try_perform_cycle(), Ok(StepOutcome::Halted));
+}
+
+
+/*** Check invalid opcode policies ***/
+
+#[test]
+fn check_invalid_opcode_halts_by_default() {
+    let mut cpu = setup_blank_cpu();
+    assert_eq!(cpu.try_interpret(0x0123), 
+               Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0x0123 }));
+    assert_eq!(cpu.try_interpret(0x5121), 
+               Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0x5121 }));
+}
+
+#[test]
+fn check_invalid_opcode_skip() {
+    let mut cpu = setup_blank_cpu();
+    cpu.set_invalid_opcode_policy(InvalidOpcodePolicy::Skip);
+    let before_pc = cpu.get_pc();
+    assert_eq!(cpu.try_interpret(0x0123), Ok(StepOutcome::Continue));
+    assert_eq!(cpu.try_interpret(0xFFFF), Ok(StepOutcome::Continue));
+    assert_eq!(cpu.get_pc(), before_pc + 4);
+}
+
+#[test]
+fn check_invalid_opcode_trap() {
+    let mut cpu = setup_blank_cpu();
+    let trapped = Rc::new(Cell::new((0u16, 0u16)));
+    let handler_trapped = trapped.clone();
+    cpu.set_invalid_opcode_policy(InvalidOpcodePolicy::Trap(
+        Box::new(move |opcode, pc| handler_trapped.set((opcode, pc)))));
+
+    assert_eq!(cpu.try_interpret(0xE1FF), Ok(StepOutcome::Continue));
+    assert_eq!(trapped.get(), (0xE1FF, 0x200));
+    assert_eq!(cpu.get_pc(), 0x202);
+}
+
+
+/*** Check timers only count down when ticked ***/
+
+#[test]
+fn check_timers_tick() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0x6A03); /* set reg A to 3 */
+    cpu.interpret(0xFA15); /* set delay timer to reg A */
+    cpu.interpret(0xFA18); /* set sound timer to reg A */
+    cpu.interpret(0xF107); /* read delay timer into reg 1 */
+    assert_eq!(cpu.get_reg(1), 3);
+
+    cpu.tick_timers();
+    assert_eq!(cpu.get_delay_timer(), 2);
+    assert_eq!(cpu.get_sound_timer(), 2);
+
+    cpu.tick_timers();
+    cpu.tick_timers();
+    cpu.tick_timers();
+    assert_eq!(cpu.get_delay_timer(), 0);
+    assert_eq!(cpu.get_sound_timer(), 0);
+}
+
+
+/*** Check quirks ***/
+
+#[test]
+fn check_shift_quirk() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0x6181); /* set reg 1 to 0x81 */
+    cpu.interpret(0x6204); /* set reg 2 to 0x04 */
+    cpu.interpret(0x8126); /* shift right */
+    assert_eq!(cpu.get_reg(1), 0x40);
+    assert_eq!(cpu.get_reg(0xF), 1);
+
+    let mut cpu = setup_cpu_with_quirks(Quirks { shift_uses_vy: true, .. Quirks::default() }, &[]);
+    cpu.interpret(0x6181); 
+    cpu.interpret(0x6284); 
+    cpu.interpret(0x812E); /* shift left */
+    assert_eq!(cpu.get_reg(1), 0x08);
+    assert_eq!(cpu.get_reg(0xF), 1);
+}
+
+#[test]
+fn check_load_store_quirk() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0xA300);
+    cpu.interpret(0xF255);
+    assert_eq!(cpu.get_index_reg(), 0x300);
+
+    let mut cpu = setup_cpu_with_quirks(
+        Quirks { load_store_increments_i: true, .. Quirks::default() }, &[]);
+    cpu.interpret(0xA300);
+    cpu.interpret(0xF255);
+    assert_eq!(cpu.get_index_reg(), 0x303);
+    cpu.interpret(0xF065);
+    assert_eq!(cpu.get_index_reg(), 0x304);
+}
+
+#[test]
+fn check_jump_quirk() {
+    let mut cpu = setup_cpu_with_quirks(Quirks { jump_uses_vx: true, .. Quirks::default() }, &[]);
+    cpu.interpret(0x6010); /* set reg 0 to 0x10 */
+    cpu.interpret(0x6320); /* set reg 3 to 0x20 */
+    cpu.interpret(0xB351);
+    assert_eq!(cpu.get_pc(), 0x351 + 0x20);
+}
+
+#[test]
+fn check_vf_reset_quirk() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0x6F05);
+    cpu.interpret(0x8011);
+    assert_eq!(cpu.get_reg(0xF), 5);
+
+    let mut cpu = setup_cpu_with_quirks(Quirks { logic_resets_vf: true, .. Quirks::default() }, &[]);
+    for opcode in [0x8011, 0x8012, 0x8013].iter() {
+        cpu.interpret(0x6F05);
+        cpu.interpret(*opcode);
+        assert_eq!(cpu.get_reg(0xF), 0);
+    }
+}
+
+#[test]
+fn check_sprite_clip_and_wrap() {
+    /* Draw the "0" font sprite at 62,30 so it goes 
+     * off the right and bottom edges of the screen */
+    for &clip in [true, false].iter() {
+        let mut cpu = setup_cpu_with_quirks(Quirks { clip_sprites: clip, .. Quirks::default() }, &[]);
+        cpu.interpret(0x603E); /* set reg 0 to 62 */
+        cpu.interpret(0x611E); /* set reg 1 to 30 */
+        cpu.interpret(0xA000); /* point I at "0" sprite */
+        cpu.interpret(0xD015);
+        /* Drawing at x = 0, y = 0 collides only if the sprite wrapped */
+        cpu.interpret(0x6000);
+        cpu.interpret(0x6100);
+        cpu.interpret(0xD015);
+        assert_eq!(cpu.get_reg(0xF), if clip {0} else {1});
+    }
+}
+
+#[test]
+fn check_sprite_position_wraps() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0x6044); /* set reg 0 to 68, wraps to x = 4 */
+    cpu.interpret(0x6122); /* set reg 1 to 34, wraps to y = 2 */
+    cpu.interpret(0xA000);
+    cpu.interpret(0xD015);
+    cpu.interpret(0x6004);
+    cpu.interpret(0x6102);
+    cpu.interpret(0xD015);
+    assert_eq!(cpu.get_reg(0xF), 1);
+}
+
+#[test]
+fn check_display_wait_quirk() {
+    let mut cpu = setup_cpu_with_quirks(Quirks { display_wait: true, .. Quirks::default() }, &[]);
+    assert_eq!(cpu.try_interpret(0xD011), Ok(StepOutcome::WaitingForVBlank));
+    assert_eq!(cpu.try_perform_cycle(), Ok(StepOutcome::WaitingForVBlank));
+    assert_eq!(cpu.get_pc(), 0x202);
+
+    cpu.set_invalid_opcode_policy(InvalidOpcodePolicy::Skip);
+    cpu.tick_timers();
+    assert_eq!(cpu.try_perform_cycle(), Ok(StepOutcome::Continue));
+    assert_eq!(cpu.get_pc(), 0x204);
+}
+
+#[test]
+fn check_lores_scroll_halved_quirk() {
+    /* Draw the top line of "0" (0xF0) at the origin and scroll it */
+    for &(halved, shift) in &[(false, 4), (true, 2)] {
+        let mut cpu = setup_cpu_with_quirks(
+            Quirks { lores_scroll_halved: halved, .. Quirks::default() }, &[]);
+        cpu.interpret(0xA000);
+        cpu.interpret(0xD011);
+        cpu.interpret(0x00FB);
+        cpu.interpret(0x00C4);
+        assert_eq!(cpu.graphics.pixel(shift, shift), 1);
+        assert_eq!(cpu.graphics.pixel(shift - 1, shift), 0);
+        assert_eq!(cpu.graphics.pixel(shift, shift - 1), 0);
+
+        /* Hires scrolls by the full amount */
+        cpu.interpret(0x00FF);
+        cpu.interpret(0x00E0);
+        cpu.interpret(0xD011);
+        cpu.interpret(0x00FB);
+        assert_eq!(cpu.graphics.pixel(4, 0), 1);
+        assert_eq!(cpu.graphics.pixel(3, 0), 0);
+    }
+}
+
+
+#[test]
+fn check_framebuffer_view() {
+    let mut cpu = setup_blank_cpu();
+    let version = cpu.framebuffer().version();
+    cpu.interpret(0xA000);
+    cpu.interpret(0xD011);
+    assert!(cpu.framebuffer().version() > version);
+    assert_eq!(cpu.framebuffer().resolution(), Resolution::Low);
+    assert_eq!(&cpu.framebuffer().row(0)[.. 6], &[1, 1, 1, 1, 0, 0]);
+
+    cpu.interpret(0x00FF);
+    assert_eq!(cpu.framebuffer().resolution(), Resolution::High);
+    assert_eq!((cpu.framebuffer().width(), cpu.framebuffer().height()), (128, 64));
+}
+
+#[test]
+fn check_scroll_up() {
+    let mut cpu = setup_blank_cpu();
+    cpu.interpret(0xA000);
+    cpu.interpret(0x6103);
+    cpu.interpret(0xD011);
+    cpu.interpret(0x00B2);
+    assert_eq!(cpu.graphics.pixel(0, 1), 1);
+    assert_eq!(cpu.graphics.pixel(0, 3), 0);
+}
+
+
+/* CPU for the given platform with 16x16 sprite data at 0x300,
+ * each row having only its leftmost and rightmost pixels set */
+fn setup_cpu_with_wide_sprite(platform: Platform) -> CPU {
+    let mut cpu = setup_cpu(platform, &[]);
+    for row in 0 .. 16 {
+        cpu.mem[0x300 + row * 2] = 0x80;
+        cpu.mem[0x301 + row * 2] = 0x01;
+    }
+    cpu.interpret(0xA300);
+    cpu
+}
+
+#[test]
+fn check_draw_16x16_sprite() {
+    let mut cpu = setup_cpu_with_wide_sprite(Platform::Schip11);
+    cpu.interpret(0x00FF);
+    cpu.interpret(0xD010);
+    for y in 0 .. 16 {
+        assert_eq!(cpu.graphics.pixel(0, y), 1);
+        assert_eq!(cpu.graphics.pixel(15, y), 1);
+        assert_eq!(cpu.graphics.pixel(8, y), 0);
+    }
+    assert_eq!(cpu.graphics.pixel(0, 16), 0);
+    assert_eq!(cpu.get_reg(0xF), 0);
+}
+
+#[test]
+fn check_lores_8x16_sprite_quirk() {
+    /* SCHIP 1.1 draws 8x16 with 1 byte per row, 
+     * so rows alternate between 0x80 and 0x01 */
+    let mut cpu = setup_cpu_with_wide_sprite(Platform::Schip11);
+    cpu.interpret(0xD010);
+    assert_eq!((cpu.graphics.pixel(0, 0), cpu.graphics.pixel(7, 0)), (1, 0));
+    assert_eq!((cpu.graphics.pixel(0, 1), cpu.graphics.pixel(7, 1)), (0, 1));
+    assert_eq!(cpu.graphics.pixel(15, 0), 0);
+    assert_eq!(cpu.graphics.pixel(7, 15), 1);
+
+    let mut cpu = setup_cpu_with_wide_sprite(Platform::SchipModern);
+    cpu.interpret(0xD010);
+    assert_eq!((cpu.graphics.pixel(0, 15), cpu.graphics.pixel(15, 15)), (1, 1));
+    assert_eq!(cpu.graphics.pixel(7, 0), 0);
+}
+
+#[test]
+fn check_dxy0_draws_nothing_without_schip() {
+    for &platform in &[Platform::CosmacVip, Platform::Chip48] {
+        let mut cpu = setup_cpu_with_wide_sprite(platform);
+        cpu.interpret(0xD010);
+        for y in 0 .. 16 {
+            for x in 0 .. 16 {
+                assert_eq!(cpu.graphics.pixel(x, y), 0);
+            }
+        }
+        assert_eq!(cpu.get_reg(0xF), 0);
+    }
+}
+
+#[test]
+fn check_16x16_sprite_clip_and_wrap() {
+    /* x = 120, y = 56 */
+    for &(platform, wrapped) in &[(Platform::SchipModern, 0), (Platform::XoChip, 1)] {
+        let mut cpu = setup_cpu_with_wide_sprite(platform);
+        cpu.interpret(0x00FF);
+        cpu.interpret(0x6078);
+        cpu.interpret(0x6138);
+        cpu.interpret(0xD010);
+        assert_eq!(cpu.graphics.pixel(120, 56), 1);
+        assert_eq!(cpu.graphics.pixel(127, 63), 0);
+        assert_eq!(cpu.graphics.pixel(7, 56), wrapped);
+        assert_eq!(cpu.graphics.pixel(120, 0), wrapped);
+    }
+}
+
+#[test]
+fn check_vf_counts_rows_quirk() {
+    /* Draw at y = 60 with 12 rows clipped off the bottom of the
+     * screen, then again at y = 58 with 4 rows colliding and 10 
+     * rows clipped */
+    let mut cpu = setup_cpu_with_wide_sprite(Platform::Schip11);
+    cpu.interpret(0x00FF);
+    cpu.interpret(0x613C);
+    cpu.interpret(0xD010);
+    assert_eq!(cpu.get_reg(0xF), 12);
+    cpu.interpret(0x613A);
+    cpu.interpret(0xD010);
+    assert_eq!(cpu.get_reg(0xF), 14);
+
+    let mut cpu = setup_cpu_with_wide_sprite(Platform::SchipModern);
+    cpu.interpret(0x00FF);
+    cpu.interpret(0x613C);
+    cpu.interpret(0xD010);
+    assert_eq!(cpu.get_reg(0xF), 0);
+    cpu.interpret(0x613A);
+    cpu.interpret(0xD010);
+    assert_eq!(cpu.get_reg(0xF), 1);
+}
+
+
+/*** Check platform profiles ***/
+
+#[test]
+fn check_vip_has_no_schip_instructions() {
+    let mut cpu = setup_cpu(Platform::CosmacVip, &[]);
+    assert_eq!(cpu.try_interpret(0x00FF), 
+               Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0x00FF }));
+
+    let mut cpu = setup_cpu(Platform::Schip11, &[]);
+    assert_eq!(cpu.try_interpret(0x00FF), Ok(StepOutcome::Continue));
+}
+
+#[test]
+fn check_scroll_up_platforms() {
+    let mut cpu = setup_cpu(Platform::Schip11, &[]);
+    assert_eq!(cpu.try_interpret(0x00B1), 
+               Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0x00B1 }));
+
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    assert_eq!(cpu.try_interpret(0x00B1), Ok(StepOutcome::Continue));
+}
+
+#[test]
+fn check_schip_doubled_lores() {
+    /* Top line of "0" drawn in lores and then hires */
+    let mut cpu = setup_cpu(Platform::Schip11, &[]);
+    cpu.interpret(0xA000);
+    cpu.interpret(0xD011);
+    cpu.interpret(0x00FF);
+    assert_eq!((cpu.graphics.pixel(7, 1), cpu.graphics.pixel(8, 0)), (1, 0));
+    cpu.interpret(0xD011);
+    assert_eq!(cpu.get_reg(0xF), 1);
+
+    /* Lores scrolls are by hires pixels */
+    cpu.interpret(0x00FE);
+    cpu.interpret(0x00FB);
+    cpu.interpret(0x00FF);
+    assert_eq!((cpu.graphics.pixel(4, 0), cpu.graphics.pixel(8, 0)), (0, 1));
+    assert_eq!(cpu.graphics.pixel(11, 1), 1);
+}
+
+#[test]
+fn check_mode_switch_clears() {
+    for &(platform, cleared) in &[(Platform::Schip11, false), (Platform::XoChip, true)] {
+        let mut cpu = setup_cpu(platform, &[]);
+        cpu.interpret(0xA000);
+        cpu.interpret(0xD011);
+        cpu.interpret(0x00FF);
+        cpu.interpret(0x00FE);
+        assert_eq!(cpu.graphics.pixel(0, 0) == 0, cleared);
+    }
+}
+
+#[test]
+fn check_vip_stack_depth() {
+    let mut cpu = setup_cpu(Platform::CosmacVip, &[]);
+    for _ in 0 .. 12 {
+        cpu.interpret(0x2400);
+    }
+    assert_eq!(cpu.try_interpret(0x2400), 
+               Err(CpuError::StackOverflow { pc: 0x400 }));
+}
+
+#[test]
+fn check_vip_font() {
+    let cpu = setup_cpu(Platform::CosmacVip, &[]);
+    assert_eq!(cpu.get_mem(5), 0x60); /* top of "1" */
+    let cpu = setup_cpu(Platform::Chip48, &[]);
+    assert_eq!(cpu.get_mem(5), 0x20);
+}
+
+
+/*** Check XO-CHIP ***/
+
+#[test]
+fn check_xo_chip_memory_size() {
+    assert_eq!(setup_cpu(Platform::XoChip, &[]).mem_size(), 0x10000);
+    assert_eq!(setup_blank_cpu().mem_size(), 0x1000);
+}
+
+#[test]
+fn check_long_index_load() {
+    let mut cpu = setup_cpu(Platform::XoChip, &[0xF0, 0x00, 0xAB, 0xCD]);
+    cpu.perform_cycle();
+    assert_eq!(cpu.get_index_reg(), 0xABCD);
+    assert_eq!(cpu.get_pc(), 0x204);
+
+    let mut cpu = setup_cpu(Platform::SchipModern, &[]);
+    assert_eq!(cpu.try_interpret(0xF000), 
+               Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0xF000 }));
+}
+
+#[test]
+fn check_skip_over_long_index_load() {
+    /* skip if V0 == 0, F000 NNNN */
+    let mut cpu = setup_cpu(Platform::XoChip, &[0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD]);
+    cpu.perform_cycle();
+    assert_eq!(cpu.get_pc(), 0x206);
+    assert_eq!(cpu.get_index_reg(), 0);
+}
+
+#[test]
+fn check_store_load_reg_range() {
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    cpu.interpret(0x6211);
+    cpu.interpret(0x6322);
+    cpu.interpret(0x6433);
+    cpu.interpret(0xA300);
+    cpu.interpret(0x5242); /* store V2 - V4 */
+    assert_eq!(cpu.get_index_reg(), 0x300);
+    assert_eq!((cpu.get_mem(0x300), cpu.get_mem(0x301), cpu.get_mem(0x302)),
+               (0x11, 0x22, 0x33));
+
+    cpu.interpret(0x5A83); /* load VA - V8 in reverse */
+    assert_eq!((cpu.get_reg(0xA), cpu.get_reg(0x9), cpu.get_reg(0x8)),
+               (0x11, 0x22, 0x33));
+}
+
+#[test]
+fn check_audio_pattern_and_pitch() {
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    assert_eq!(cpu.sound().pitch, 64);
+    assert!(!cpu.sound().playing);
+
+    cpu.interpret(0xA000);
+    cpu.interpret(0xF002);
+    assert_eq!(&cpu.sound().pattern[.. 5], &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+    cpu.interpret(0x6370);
+    cpu.interpret(0xF33A);
+    assert_eq!(cpu.sound().pitch, 0x70);
+    cpu.interpret(0xF318);
+    assert!(cpu.sound().playing);
+
+    let mut cpu = setup_cpu(Platform::SchipModern, &[]);
+    assert_eq!(cpu.try_interpret(0xF002), 
+               Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0xF002 }));
+}
+
+#[test]
+fn check_planes_collide_separately() {
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    cpu.interpret(0xA000); /* font for "0", first line 0xF0 */
+    cpu.interpret(0xF201); /* select second plane */
+    cpu.interpret(0xD011);
+    assert_eq!(cpu.get_reg(0xF), 0);
+
+    cpu.interpret(0xF101); /* first plane is still blank */
+    cpu.interpret(0xD011);
+    assert_eq!(cpu.get_reg(0xF), 0);
+
+    cpu.interpret(0xF301); /* collides on either plane */
+    cpu.interpret(0xD011);
+    assert_eq!(cpu.get_reg(0xF), 1);
+}
+
+#[test]
+fn check_sprite_data_per_plane() {
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    cpu.interpret(0xA000);
+    cpu.interpret(0xF301);
+    /* 0xF0 on the first plane, 0x90 on the second */
+    cpu.interpret(0xD011);
+    cpu.interpret(0xF201);
+    cpu.interpret(0xA005); /* "1", first line 0x20 */
+    cpu.interpret(0xD011);
+    assert_eq!(cpu.get_reg(0xF), 0);
+
+    cpu.interpret(0xA001); /* second line of "0", 0x90 */
+    cpu.interpret(0xD011);
+    assert_eq!(cpu.get_reg(0xF), 1);
+}
+
+#[test]
+fn check_clear_selected_planes() {
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    cpu.interpret(0xA000);
+    cpu.interpret(0xF301);
+    cpu.interpret(0xD011);
+    cpu.interpret(0xF101);
+    cpu.interpret(0x00E0);
+    cpu.interpret(0xD011); /* first plane was cleared */
+    assert_eq!(cpu.get_reg(0xF), 0);
+
+    cpu.interpret(0xF201);
+    cpu.interpret(0xD011); /* second plane wasn't */
+    assert_eq!(cpu.get_reg(0xF), 1);
+}
+
+
+/* Random source returning a fixed sequence */
+struct ScriptedRandom {
+    values: Vec<u8>,
+    next: usize
+}
+
+impl RandomSource for ScriptedRandom {
+    fn next_u8(&mut self) -> u8 {
+        self.next += 1;
+        self.values[(self.next - 1) % self.values.len()]
+    }
+    fn state(&self) -> u64 { self.next as u64 }
+    fn set_state(&mut self, state:u64) { self.next = state as usize; }
+}
+
+#[test]
+fn check_random_seed_reproducible() {
+    let mut first = setup_blank_cpu();
+    let mut second = setup_blank_cpu();
+    first.seed_random(42);
+    second.seed_random(42);
+    for _ in 0 .. 8 {
+        first.interpret(0xC0FF);
+        second.interpret(0xC0FF);
+        assert_eq!(first.get_reg(0), second.get_reg(0));
+    }
+}
+
+#[test]
+fn check_random_source_replaceable() {
+    let mut cpu = setup_blank_cpu();
+    cpu.set_random_source(Box::new(ScriptedRandom { values: vec![0xAB, 0xFF], next: 0 }));
+    cpu.interpret(0xC00F);
+    assert_eq!(cpu.get_reg(0), 0x0B);
+    cpu.interpret(0xC1F0);
+    assert_eq!(cpu.get_reg(1), 0xF0);
+
+    /* The source's state is part of save states */
+    let state = cpu.save_state();
+    cpu.interpret(0xC2FF);
+    assert_eq!(cpu.get_reg(2), 0xAB);
+    cpu.load_state(&state).unwrap();
+    cpu.interpret(0xC2FF);
+    assert_eq!(cpu.get_reg(2), 0xAB);
+    cpu.interpret(0xC2FF);
+    assert_eq!(cpu.get_reg(2), 0xFF);
+}
+
+#[test]
+fn check_hp_flags_restore_low_registers() {
+    let mut cpu = setup_cpu(Platform::SchipModern, &[]);
+    for reg in 0 .. 16 {
+        cpu.interpret(0x6000 | (reg << 8) | (reg + 1));
+    }
+    cpu.interpret(0xF375); /* store V0 - V3 */
+    assert_eq!(cpu.hp_48_flags(), &[1, 2, 3, 4, 0, 0, 0, 0]);
+
+    for reg in 0 .. 16 {
+        cpu.interpret(0x6000 | (reg << 8));
+    }
+    cpu.interpret(0xF285); /* load V0 - V2 */
+    assert_eq!((cpu.get_reg(0), cpu.get_reg(1), cpu.get_reg(2)), (1, 2, 3));
+    assert_eq!(cpu.get_reg(3), 0);
+}
+
+#[test]
+fn check_hp_flags_count_per_platform() {
+    let mut cpu = setup_cpu(Platform::SchipModern, &[]);
+    cpu.set_hp_48_flags(&[9; 16]);
+    assert_eq!(cpu.hp_48_flags(), &[9; 8]);
+    cpu.interpret(0xFF85);
+    assert_eq!(cpu.get_reg(7), 9);
+    assert_eq!(cpu.get_reg(8), 0);
+
+    let mut cpu = setup_cpu(Platform::XoChip, &[]);
+    cpu.set_hp_48_flags(&[9; 16]);
+    cpu.interpret(0xFF85);
+    assert_eq!(cpu.get_reg(0xF), 9);
+}
+
+}
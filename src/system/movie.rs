@@ -0,0 +1,447 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use super::{CPU, CpuError, Platform};
+use super::graphics::{Display, Graphics, Resolution, PLANE_COUNT, FRAMEBUFFER_ROWS};
+use super::hash::Fnv1a;
+use super::io::movie_io::MovieKeypad;
+use super::scheduler::{Scheduler, SystemClock};
+use rom;
+
+/// First line of every movie file.
+const MOVIE_MAGIC : &str = "schip8-movie";
+
+/// Version of the movie format written by `Movie::write`.
+pub const MOVIE_VERSION : u32 = 2;
+
+
+/// Reason a movie couldn't be read, or stopped matching the recording.
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    /// The movie file is malformed at the given line, numbered from 1.
+    Parse { line: usize, message: &'static str },
+    /// The movie was written in a format version this build can't read.
+    UnsupportedVersion { version: u32 },
+    /// The movie was recorded with a different ROM, hashes are `rom::rom_hash`.
+    RomMismatch { expected: u64, found: u64 },
+    /// The framebuffer at the end of a frame, numbered from 0, doesn't
+    /// match the recording.
+    Desync { frame: usize, expected: u64, found: u64 },
+    /// The program faulted during playback.
+    Cpu(CpuError)
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Io(ref e) =>
+                write!(f, "Movie file error: {}", e),
+            MovieError::Parse { line, message } =>
+                write!(f, "Invalid movie at line {}: {}", line, message),
+            MovieError::UnsupportedVersion { version } =>
+                write!(f, "Movie format version {} is not supported, expected {} or older",
+                       version, MOVIE_VERSION),
+            MovieError::RomMismatch { expected, found } =>
+                write!(f, "Movie was recorded with a different ROM, ROM hash {:016X} \
+                           but {:016X} was recorded", found, expected),
+            MovieError::Desync { frame, expected, found } =>
+                write!(f, "Playback desynced at frame {}, framebuffer hash {:016X} \
+                           but {:016X} was recorded", frame, found, expected),
+            MovieError::Cpu(ref e) =>
+                write!(f, "Execution stopped during playback: {}", e),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> MovieError {
+        MovieError::Io(e)
+    }
+}
+
+
+/// FNV-1a hash of the resolution and every plane of the framebuffer.
+pub fn framebuffer_hash(graphics: &Graphics) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(&[if graphics.resolution() == Resolution::High {1} else {0}]);
+    for plane in 0 .. PLANE_COUNT {
+        for y in 0 .. FRAMEBUFFER_ROWS {
+            hasher.write(&graphics.plane_row(plane, y).to_le_bytes());
+        }
+    }
+    hasher.finish()
+}
+
+
+/// Keypad state of a recorded frame and the framebuffer it produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieFrame {
+    /// Keys held down during the frame, bit n set for key n.
+    pub keys: u16,
+    /// `framebuffer_hash` at the end of the frame.
+    pub framebuffer_hash: u64
+}
+
+
+/// Recorded session, everything needed to reproduce it exactly
+/// given the same ROM.
+///
+/// Movies are saved as text so they can be attached to bug reports:
+/// a header of the format version, platform, instructions per second,
+/// random seed and ROM hash followed by a line per frame with the
+/// keypad state and framebuffer hash in hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub platform: Platform,
+    pub instructions_per_second: u64,
+    pub seed: u64,
+    /// `rom::rom_hash` of the recorded ROM, version 1 movies don't have one.
+    pub rom_hash: Option<u64>,
+    pub frames: Vec<MovieFrame>
+}
+
+/* Parse header line `number` of the form "name value" */
+fn parse_field<T: ::std::str::FromStr>(line: Option<String>, number: usize, name: &str,
+                                       message: &'static str) -> Result<T, MovieError> {
+    let line = line.unwrap_or_default();
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next().and_then(|v| v.parse().ok()), words.next()) {
+        (Some(n), Some(value), None) if n == name => Ok(value),
+        _ => Err(MovieError::Parse { line: number, message })
+    }
+}
+
+fn parse_frame(number: usize, line: &str) -> Result<MovieFrame, MovieError> {
+    let mut words = line.split_whitespace();
+    let keys = words.next().and_then(|k| u16::from_str_radix(k, 16).ok());
+    let hash = words.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+    match (keys, hash, words.next()) {
+        (Some(keys), Some(hash), None) => Ok(MovieFrame { keys, framebuffer_hash: hash }),
+        _ => Err(MovieError::Parse { line: number, message: "expected keys and framebuffer hash" })
+    }
+}
+
+impl Movie {
+
+    pub fn new(platform: Platform, instructions_per_second: u64, seed: u64,
+               rom_hash: u64) -> Movie {
+        Movie { platform,
+                instructions_per_second,
+                seed,
+                rom_hash: Some(rom_hash),
+                frames: Vec::new() }
+    }
+
+    /// Check the movie was recorded with the given ROM, always
+    /// passes for movies which don't record the ROM hash.
+    pub fn check_rom(&self, rom_contents: &[u8]) -> Result<(), MovieError> {
+        let found = rom::rom_hash(rom_contents);
+        match self.rom_hash {
+            Some(expected) if expected != found =>
+                Err(MovieError::RomMismatch { expected, found }),
+            _ => Ok(())
+        }
+    }
+
+    /// Create a CPU set up as when the movie was recorded, reading
+    /// keys from the given keypad. Memory should be loaded with
+    /// the platform's memory size.
+    pub fn create_cpu(&self, mem: Vec<u8>, display: Box<dyn Display>, keypad: MovieKeypad) -> CPU {
+        let mut cpu = CPU::with_platform(mem, self.platform, display, Box::new(keypad));
+        cpu.seed_random(self.seed);
+        cpu
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{} {}", MOVIE_MAGIC, MOVIE_VERSION)?;
+        writeln!(out, "platform {}", self.platform.name())?;
+        writeln!(out, "ips {}", self.instructions_per_second)?;
+        writeln!(out, "seed {}", self.seed)?;
+        if let Some(rom_hash) = self.rom_hash {
+            writeln!(out, "rom {:016X}", rom_hash)?;
+        }
+        for frame in self.frames.iter() {
+            writeln!(out, "{:04X} {:016X}", frame.keys, frame.framebuffer_hash)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(input: R) -> Result<Movie, MovieError> {
+        let mut lines = input.lines();
+        let mut next_line = || lines.next().map_or(Ok(None), |l| l.map(Some));
+
+        let version = parse_field(next_line()?, 1, MOVIE_MAGIC, "not a movie file")?;
+        if version == 0 || version > MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion { version });
+        }
+
+        let platform : String = parse_field(next_line()?, 2, "platform", "expected platform")?;
+        let platform = Platform::from_name(&platform)
+            .ok_or(MovieError::Parse { line: 2, message: "unknown platform" })?;
+        let ips = parse_field(next_line()?, 3, "ips", "expected instructions per second")?;
+        let seed = parse_field(next_line()?, 4, "seed", "expected random seed")?;
+        let mut movie = Movie { platform,
+                                instructions_per_second: ips,
+                                seed,
+                                rom_hash: None,
+                                frames: Vec::new() };
+
+        let mut number = 4;
+        if version >= 2 {
+            let rom_hash : String = parse_field(next_line()?, 5, "rom", "expected ROM hash")?;
+            movie.rom_hash = Some(u64::from_str_radix(&rom_hash, 16)
+                .map_err(|_| MovieError::Parse { line: 5, message: "expected ROM hash" })?);
+            number += 1;
+        }
+        while let Some(line) = next_line()? {
+            number += 1;
+            if !line.trim().is_empty() {
+                movie.frames.push(parse_frame(number, &line)?);
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(file_path)?);
+        self.write(&mut out)?;
+        out.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(file_path: P) -> Result<Movie, MovieError> {
+        Movie::read(BufReader::new(File::open(file_path)?))
+    }
+}
+
+
+/// Records the keypad state of each frame into a movie. The CPU
+/// must be created with `create_cpu` so it only sees the keys
+/// given to `start_frame`.
+pub struct MovieRecorder {
+    movie: Movie,
+    keypad: MovieKeypad
+}
+
+impl MovieRecorder {
+
+    pub fn new(platform: Platform, instructions_per_second: u64, seed: u64,
+               rom_hash: u64) -> MovieRecorder {
+        MovieRecorder { movie: Movie::new(platform, instructions_per_second, seed, rom_hash),
+                        keypad: MovieKeypad::new() }
+    }
+
+    pub fn create_cpu(&self, mem: Vec<u8>, display: Box<dyn Display>) -> CPU {
+        self.movie.create_cpu(mem, display, self.keypad.clone())
+    }
+
+    /// Set the keys held for the next frame, call before running it.
+    pub fn start_frame(&mut self, keys: u16) {
+        self.keypad.set_keys(keys);
+    }
+
+    /// Record the frame once it has been run.
+    pub fn end_frame(&mut self, cpu: &CPU) {
+        self.movie.frames.push(MovieFrame { keys: self.keypad.keys(),
+                                            framebuffer_hash: framebuffer_hash(cpu.framebuffer()) });
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+
+/// Replays a movie's keypad state a frame at a time, checking
+/// the framebuffer matches the recording after each frame.
+pub struct MoviePlayer {
+    movie: Movie,
+    keypad: MovieKeypad,
+    frame: usize
+}
+
+impl MoviePlayer {
+
+    pub fn new(movie: Movie) -> MoviePlayer {
+        MoviePlayer { movie, keypad: MovieKeypad::new(), frame: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn create_cpu(&self, mem: Vec<u8>, display: Box<dyn Display>) -> CPU {
+        self.movie.create_cpu(mem, display, self.keypad.clone())
+    }
+
+    /// Set the keys of the next frame, returns false
+    /// once every frame has been played.
+    pub fn start_frame(&mut self) -> bool {
+        match self.movie.frames.get(self.frame) {
+            Some(frame) => {
+                self.keypad.set_keys(frame.keys);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Check the framebuffer after running the frame matches the recording.
+    pub fn end_frame(&mut self, cpu: &CPU) -> Result<(), MovieError> {
+        let frame = self.frame;
+        self.frame += 1;
+        let expected = self.movie.frames[frame].framebuffer_hash;
+        let found = framebuffer_hash(cpu.framebuffer());
+        if found != expected {
+            return Err(MovieError::Desync { frame, expected, found });
+        }
+        Ok(())
+    }
+
+    /// Play the rest of the movie as fast as possible,
+    /// stopping at the first frame which doesn't match.
+    pub fn play(&mut self, cpu: &mut CPU) -> Result<(), MovieError> {
+        let mut scheduler = Scheduler::new(self.movie.instructions_per_second,
+                                           Box::new(SystemClock::new()));
+        while self.start_frame() {
+            scheduler.run_frame(cpu).map_err(MovieError::Cpu)?;
+            self.end_frame(cpu)?;
+        }
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, MovieError, MovieRecorder, MoviePlayer, MOVIE_VERSION};
+    use system::{CPU, Platform};
+    use system::scheduler::{Scheduler, SystemClock};
+    use system::graphics::graphics_null::NullScreen;
+    use system::io::movie_io::MovieKeypad;
+    use system::io::Input;
+    use rom;
+
+/* loop { V0 = key, V1 = random, I = digit V1, draw at V0,V1 } */
+const PROGRAM : [u8; 10] = [0xF0, 0x0A, 0xC1, 0xFF, 0xF1, 0x29, 0xD0, 0x15, 0x12, 0x00];
+
+const KEYS : [u16; 8] = [0, 0, 0, 1 << 5, 1 << 5, 0, 1 << 0xA, 0];
+
+fn load_program() -> Vec<u8> {
+    rom::load_rom(&PROGRAM, rom::MAX_RAM).unwrap()
+}
+
+fn record_movie() -> (Movie, CPU) {
+    let mut recorder = MovieRecorder::new(Platform::SchipModern, 600, 1234,
+                                          rom::rom_hash(&PROGRAM));
+    let mut cpu = recorder.create_cpu(load_program(), Box::new(NullScreen::new()));
+    let mut scheduler = Scheduler::new(600, Box::new(SystemClock::new()));
+    for keys in KEYS.iter() {
+        recorder.start_frame(*keys);
+        scheduler.run_frame(&mut cpu).unwrap();
+        recorder.end_frame(&cpu);
+    }
+    (recorder.movie().clone(), cpu)
+}
+
+#[test]
+fn check_wait_for_key_does_not_block() {
+    let (movie, cpu) = record_movie();
+    assert_eq!(movie.frames.len(), KEYS.len());
+    /* Nothing drawn until a key is pressed in frame 3 */
+    assert_eq!(movie.frames[0].framebuffer_hash, movie.frames[2].framebuffer_hash);
+    assert!(movie.frames[2].framebuffer_hash != movie.frames[3].framebuffer_hash);
+    assert_eq!(cpu.get_reg(0), 0xA);
+
+    let mut keypad = MovieKeypad::new();
+    keypad.set_keys(0b1100);
+    assert_eq!(keypad.poll_key(), Some(2));
+    keypad.set_keys(0);
+    assert_eq!(keypad.poll_key(), None);
+}
+
+#[test]
+fn check_playback_matches_recording() {
+    let (movie, recorded) = record_movie();
+
+    let mut text = Vec::new();
+    movie.write(&mut text).unwrap();
+    let movie = Movie::read(&text[..]).unwrap();
+    assert_eq!(movie.platform, Platform::SchipModern);
+    assert_eq!(movie.seed, 1234);
+    assert_eq!(movie.rom_hash, Some(rom::rom_hash(&PROGRAM)));
+
+    let mut player = MoviePlayer::new(movie);
+    let mut cpu = player.create_cpu(load_program(), Box::new(NullScreen::new()));
+    player.play(&mut cpu).unwrap();
+    assert_eq!(cpu.save_state(), recorded.save_state());
+}
+
+#[test]
+fn check_desync_detected() {
+    let (mut movie, _) = record_movie();
+    movie.frames[4].keys = 1 << 6;
+
+    let mut player = MoviePlayer::new(movie);
+    let mut cpu = player.create_cpu(load_program(), Box::new(NullScreen::new()));
+    match player.play(&mut cpu) {
+        Err(MovieError::Desync { frame, .. }) => assert_eq!(frame, 4),
+        r => panic!("unexpected {:?}", r)
+    }
+}
+
+#[test]
+fn check_bad_movies_rejected() {
+    match Movie::read(&b"not a movie\n"[..]) {
+        Err(MovieError::Parse { line, .. }) => assert_eq!(line, 1),
+        r => panic!("unexpected {:?}", r)
+    }
+
+    let newer = format!("schip8-movie {}\nplatform schip\nips 600\nseed 1\n", MOVIE_VERSION + 1);
+    match Movie::read(newer.as_bytes()) {
+        Err(MovieError::UnsupportedVersion { version }) => assert_eq!(version, MOVIE_VERSION + 1),
+        r => panic!("unexpected {:?}", r)
+    }
+
+    let bad_frame = "schip8-movie 1\nplatform schip\nips 600\nseed 1\n0010 00FF\nzz\n";
+    match Movie::read(bad_frame.as_bytes()) {
+        Err(MovieError::Parse { line, .. }) => assert_eq!(line, 6),
+        r => panic!("unexpected {:?}", r)
+    }
+
+    let no_rom = "schip8-movie 2\nplatform schip\nips 600\nseed 1\n0010 00FF\n";
+    match Movie::read(no_rom.as_bytes()) {
+        Err(MovieError::Parse { line, .. }) => assert_eq!(line, 5),
+        r => panic!("unexpected {:?}", r)
+    }
+}
+
+#[test]
+fn check_rom_mismatch_rejected() {
+    let (movie, _) = record_movie();
+    assert!(movie.check_rom(&PROGRAM).is_ok());
+
+    let mut other = PROGRAM;
+    other[1] = 0x0B;
+    match movie.check_rom(&other) {
+        Err(MovieError::RomMismatch { expected, found }) => {
+            assert_eq!(expected, rom::rom_hash(&PROGRAM));
+            assert_eq!(found, rom::rom_hash(&other));
+        },
+        r => panic!("unexpected {:?}", r)
+    }
+
+    /* Version 1 movies don't record the ROM so can't be checked */
+    let version_1 = "schip8-movie 1\nplatform schip\nips 600\nseed 1\n0010 00FF\n";
+    let movie = Movie::read(version_1.as_bytes()).unwrap();
+    assert_eq!(movie.rom_hash, None);
+    assert_eq!(movie.frames.len(), 1);
+    assert!(movie.check_rom(&other).is_ok());
+}
+
+}
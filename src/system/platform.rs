@@ -0,0 +1,203 @@
+use super::Quirks;
+use rom::{MAX_RAM, XO_MAX_RAM};
+
+/// Historical CHIP 8 interpreters the emulator can behave as,
+/// modern Super CHIP by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    /// The original CHIP 8 interpreter on the RCA COSMAC VIP.
+    CosmacVip,
+    /// CHIP-48 on the HP48 calculator.
+    Chip48,
+    /// Super CHIP 1.0 on the HP48.
+    Schip10,
+    /// Super CHIP 1.1 on the HP48.
+    Schip11,
+    /// Super CHIP as implemented by modern interpreters such as Octo.
+    #[default]
+    SchipModern,
+    /// XO-CHIP, Octo's extension of Super CHIP.
+    XoChip
+}
+
+/// Small hexadecimal font loaded at address 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    /// Font from the COSMAC VIP interpreter ROM.
+    Vip,
+    /// Font introduced by CHIP-48 and used by Super CHIP.
+    Chip48
+}
+
+/// Everything which differs between platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub quirks: Quirks,
+    /// Approximate speed of the original interpreter.
+    pub instructions_per_second: u64,
+    /// Maximum number of nested subroutine calls, at most 16.
+    pub stack_depth: usize,
+    /// Whether the Super CHIP instructions (00CN, 00FB - 00FF, DXY0,
+    /// FX30, FX75 and FX85) are available.
+    pub schip_instructions: bool,
+    /// Whether 00BN scrolls the display up N lines, an extension
+    /// to Super CHIP supported by some later interpreters.
+    pub scroll_up_instruction: bool,
+    /// Whether the XO-CHIP instructions (00DN, 5XY2, 5XY3 and F000 NNNN)
+    /// are available, this also gives 16 FX75/FX85 flags.
+    pub xo_chip_instructions: bool,
+    /// Size of memory in bytes.
+    pub memory_size: usize,
+    /// Whether the framebuffer is always 128x64 with low resolution
+    /// pixels drawn as 2x2 blocks, as on the HP48.
+    pub doubled_lores: bool,
+    pub font: Font
+}
+
+static PLATFORM_NAMES : [(&str, Platform); 6] = [
+    ("vip", Platform::CosmacVip),
+    ("chip48", Platform::Chip48),
+    ("schip1.0", Platform::Schip10),
+    ("schip1.1", Platform::Schip11),
+    ("schip", Platform::SchipModern),
+    ("xochip", Platform::XoChip)
+];
+
+impl Platform {
+
+    /// Look up a platform by its command line name, one of
+    /// "vip", "chip48", "schip1.0", "schip1.1", "schip" or "xochip".
+    pub fn from_name(name: &str) -> Option<Platform> {
+        PLATFORM_NAMES.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, platform)| platform)
+    }
+
+    pub fn name(&self) -> &'static str {
+        PLATFORM_NAMES.iter()
+            .find(|&&(_, platform)| platform == *self)
+            .map(|&(n, _)| n)
+            .unwrap()
+    }
+
+    /// Names of all platforms, for use in help messages.
+    pub fn names() -> Vec<&'static str> {
+        PLATFORM_NAMES.iter().map(|&(n, _)| n).collect()
+    }
+
+    pub fn profile(&self) -> Profile {
+        match *self {
+            Platform::CosmacVip => Profile {
+                quirks: Quirks { shift_uses_vy: true,
+                                 load_store_increments_i: true,
+                                 jump_uses_vx: false,
+                                 logic_resets_vf: true,
+                                 clip_sprites: true,
+                                 display_wait: true,
+                                 lores_scroll_halved: false,
+                                 lores_dxy0_draws_8x16: true,
+                                 vf_counts_rows: false,
+                                 mode_switch_clears: false },
+                instructions_per_second: 600,
+                stack_depth: 12,
+                schip_instructions: false,
+                scroll_up_instruction: false,
+                xo_chip_instructions: false,
+                memory_size: MAX_RAM,
+                doubled_lores: false,
+                font: Font::Vip
+            },
+
+            /* CHIP-48 and SCHIP 1.0 increment I by X rather than X + 1
+             * for FX55/FX65, as this is rarely relied on treat them as
+             * leaving I unchanged like later interpreters */
+            Platform::Chip48 => Profile {
+                quirks: Quirks { shift_uses_vy: false,
+                                 load_store_increments_i: false,
+                                 jump_uses_vx: true,
+                                 logic_resets_vf: false,
+                                 clip_sprites: true,
+                                 display_wait: false,
+                                 lores_scroll_halved: false,
+                                 lores_dxy0_draws_8x16: true,
+                                 vf_counts_rows: false,
+                                 mode_switch_clears: false },
+                instructions_per_second: 900,
+                stack_depth: 16,
+                schip_instructions: false,
+                scroll_up_instruction: false,
+                xo_chip_instructions: false,
+                memory_size: MAX_RAM,
+                doubled_lores: false,
+                font: Font::Chip48
+            },
+
+            Platform::Schip10 => Profile {
+                quirks: Quirks { lores_scroll_halved: true, 
+                                 .. Platform::Chip48.profile().quirks },
+                schip_instructions: true,
+                doubled_lores: true,
+                instructions_per_second: 1200,
+                .. Platform::Chip48.profile()
+            },
+
+            Platform::Schip11 => Profile {
+                quirks: Quirks { vf_counts_rows: true, 
+                                 .. Platform::Schip10.profile().quirks },
+                instructions_per_second: 1800,
+                .. Platform::Schip10.profile()
+            },
+
+            Platform::SchipModern => Profile {
+                quirks: Quirks { jump_uses_vx: true, 
+                                 lores_dxy0_draws_8x16: false,
+                                 mode_switch_clears: true,
+                                 .. Quirks::default() },
+                instructions_per_second: 1800,
+                stack_depth: 16,
+                schip_instructions: true,
+                scroll_up_instruction: true,
+                xo_chip_instructions: false,
+                memory_size: MAX_RAM,
+                doubled_lores: false,
+                font: Font::Chip48
+            },
+
+            Platform::XoChip => Profile {
+                quirks: Quirks { shift_uses_vy: true,
+                                 load_store_increments_i: true,
+                                 jump_uses_vx: false,
+                                 logic_resets_vf: false,
+                                 clip_sprites: false,
+                                 display_wait: false,
+                                 lores_scroll_halved: false,
+                                 lores_dxy0_draws_8x16: false,
+                                 vf_counts_rows: false,
+                                 mode_switch_clears: true },
+                instructions_per_second: 30000,
+                stack_depth: 16,
+                schip_instructions: true,
+                scroll_up_instruction: true,
+                xo_chip_instructions: true,
+                memory_size: XO_MAX_RAM,
+                doubled_lores: false,
+                font: Font::Chip48
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Platform;
+
+#[test]
+fn check_platform_names() {
+    for name in Platform::names() {
+        assert_eq!(Platform::from_name(name).unwrap().name(), name);
+    }
+    assert_eq!(Platform::from_name("schip1.1"), Some(Platform::Schip11));
+    assert_eq!(Platform::from_name("chip9"), None);
+}
+
+}
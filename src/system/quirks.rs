@@ -0,0 +1,52 @@
+/// Behaviour of instructions which differ between CHIP 8 interpreters.
+///
+/// By default sprites are clipped at the edges of the screen and DXY0
+/// draws 8x16 sprites in low resolution, every other quirk is off.
+/// `Platform::profile` gives the quirks of a particular interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY and store the result in VX, rather than
+    /// shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing after the last register
+    /// stored/loaded, rather than leaving I unchanged.
+    pub load_store_increments_i: bool,
+    /// BNNN behaves as BXNN, jumping to XNN + VX rather than NNN + V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the edges of the screen, rather than
+    /// wrapping around to the other side.
+    pub clip_sprites: bool,
+    /// DXYN waits for the start of the next frame before execution
+    /// continues, limiting drawing to one sprite per frame.
+    pub display_wait: bool,
+    /// 00CN/00FB/00FC scroll by half as many pixels in low resolution,
+    /// as SCHIP 1.1 scrolls by high resolution pixels in both modes.
+    pub lores_scroll_halved: bool,
+    /// DXY0 draws an 8x16 sprite in low resolution, rather than
+    /// a 16x16 sprite as in high resolution.
+    pub lores_dxy0_draws_8x16: bool,
+    /// In high resolution DXYN sets VF to the number of sprite rows
+    /// which collided or were clipped off the bottom of the screen,
+    /// as on SCHIP 1.1, rather than to 1 on any collision.
+    pub vf_counts_rows: bool,
+    /// 00FE/00FF clear the screen when switching resolution.
+    pub mode_switch_clears: bool
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks { shift_uses_vy: false,
+                 load_store_increments_i: false,
+                 jump_uses_vx: false,
+                 logic_resets_vf: false,
+                 clip_sprites: true,
+                 display_wait: false,
+                 lores_scroll_halved: false,
+                 lores_dxy0_draws_8x16: true,
+                 vf_counts_rows: false,
+                 mode_switch_clears: false
+        }
+    }
+}
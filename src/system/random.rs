@@ -0,0 +1,80 @@
+/* Any non zero seed works, zero would get the generator stuck */
+const ZERO_SEED_REPLACEMENT : u64 = 0x9E3779B97F4A7C15;
+
+
+/// Source of the random numbers masked by CXNN. Its state is saved
+/// with the rest of the machine, so restoring a state with `set_state`
+/// must reproduce the same sequence of numbers.
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+    fn state(&self) -> u64;
+    /// Restore a state returned by `state`, or seed the source.
+    fn set_state(&mut self, state:u64);
+}
+
+
+/// Xorshift* generator used by default, its whole state is a single
+/// `u64` which is also its seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XorshiftRng {
+    state: u64
+}
+
+impl XorshiftRng {
+
+    pub fn new(seed:u64) -> XorshiftRng {
+        let mut rng = XorshiftRng { state: 0 };
+        rng.set_state(seed);
+        rng
+    }
+}
+
+impl RandomSource for XorshiftRng {
+
+    fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state:u64) {
+        self.state = if state == 0 {ZERO_SEED_REPLACEMENT} else {state};
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{RandomSource, XorshiftRng};
+
+#[test]
+fn check_same_seed_same_sequence() {
+    let mut a = XorshiftRng::new(1234);
+    let mut b = XorshiftRng::new(1234);
+    let first : Vec<u8> = (0 .. 16).map(|_| a.next_u8()).collect();
+    let second : Vec<u8> = (0 .. 16).map(|_| b.next_u8()).collect();
+    assert_eq!(first, second);
+    assert!(first.iter().any(|&n| n != first[0]));
+
+    /* Restoring a state continues the sequence from there */
+    let state = a.state();
+    let next = a.next_u8();
+    b.set_state(state);
+    assert_eq!(b.next_u8(), next);
+}
+
+#[test]
+fn check_zero_seed() {
+    let mut rng = XorshiftRng::new(0);
+    assert!((0 .. 16).any(|_| rng.next_u8() != 0));
+}
+
+}
@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use super::CPU;
+use super::savestate::SaveStateError;
+
+
+/* Deltas are runs of unchanged bytes followed by runs of changed
+ * bytes xored with their old value, lengths are LEB128 encoded */
+
+fn write_length(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7F) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_length(delta: &[u8], pos: &mut usize) -> usize {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let b = delta[*pos];
+        *pos += 1;
+        n |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return n;
+        }
+        shift += 7;
+    }
+}
+
+/* Encode the difference between two states of the same length */
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut i = 0;
+    while i < new.len() {
+        let unchanged = old[i ..].iter().zip(new[i ..].iter()).take_while(|&(a, b)| a == b).count();
+        let start = i + unchanged;
+        let changed = old[start ..].iter().zip(new[start ..].iter()).take_while(|&(a, b)| a != b).count();
+        write_length(&mut delta, unchanged);
+        write_length(&mut delta, changed);
+        delta.extend(old[start .. start + changed].iter().zip(new[start .. start + changed].iter())
+                                                   .map(|(a, b)| a ^ b));
+        i = start + changed;
+    }
+    delta
+}
+
+/* Xor a delta into a state, turning either of the states it
+ * was encoded from into the other */
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_length(delta, &mut pos);
+        let changed = read_length(delta, &mut pos);
+        for (s, d) in state[i .. i + changed].iter_mut().zip(delta[pos .. pos + changed].iter()) {
+            *s ^= *d;
+        }
+        i += changed;
+        pos += changed;
+    }
+}
+
+
+/// Bounded history of save states for stepping a game backwards.
+///
+/// A snapshot is taken every `interval` frames. Only the newest is
+/// kept in full, older snapshots are stored as the difference from the
+/// snapshot after them, which is small as most of memory is unchanged
+/// between frames. Once `capacity` snapshots are stored the oldest is
+/// dropped for each new one.
+pub struct RewindBuffer {
+    capacity: usize,
+    interval: u64,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>, /* Oldest first, each undoes the step to the following snapshot */
+    frames_since_snapshot: u64,
+    frames_until_step: u64 /* Frames of rewinding until the next snapshot is restored */
+}
+
+impl RewindBuffer {
+
+    /// Create a buffer holding up to `capacity` snapshots taken
+    /// every `interval` frames, at least one frame apart.
+    pub fn new(capacity: usize, interval: u64) -> RewindBuffer {
+        RewindBuffer { capacity: if capacity > 0 {capacity} else {1},
+                       interval: if interval > 0 {interval} else {1},
+                       newest: None,
+                       deltas: VecDeque::new(),
+                       frames_since_snapshot: 0,
+                       frames_until_step: 0 }
+    }
+
+    /// Number of snapshots which can be rewound to.
+    pub fn len(&self) -> usize {
+        if self.newest.is_some() {self.deltas.len() + 1} else {0}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes used to store the snapshots.
+    pub fn stored_size(&self) -> usize {
+        self.newest.as_ref().map_or(0, |s| s.len()) +
+            self.deltas.iter().map(|d| d.len()).sum::<usize>()
+    }
+
+    /// Forget all snapshots.
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Take a snapshot of the CPU now.
+    pub fn push(&mut self, cpu: &CPU) {
+        let state = cpu.save_state();
+        if let Some(newest) = self.newest.take() {
+            if newest.len() == state.len() {
+                self.deltas.push_back(encode_delta(&state, &newest));
+                if self.deltas.len() >= self.capacity {
+                    self.deltas.pop_front();
+                }
+            } else {
+                self.deltas.clear();
+            }
+        }
+        self.newest = Some(state);
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Restore the newest snapshot and remove it from the buffer,
+    /// returns false if there are no snapshots left. If the snapshot
+    /// can't be loaded, such as when it was taken from a CPU for
+    /// another platform, the buffer is cleared and the error returned.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Result<bool, SaveStateError> {
+        let mut state = match self.newest.take() {
+            Some(state) => state,
+            None => return Ok(false)
+        };
+        if let Err(e) = cpu.load_state(&state) {
+            self.clear();
+            return Err(e);
+        }
+
+        if let Some(delta) = self.deltas.pop_back() {
+            apply_delta(&mut state, &delta);
+            self.newest = Some(state);
+        }
+        self.frames_since_snapshot = 0;
+        Ok(true)
+    }
+
+    /// Call at the end of each emulated frame, taking a
+    /// snapshot every `interval` frames.
+    pub fn record_frame(&mut self, cpu: &CPU) {
+        self.frames_until_step = 0;
+        self.frames_since_snapshot += 1;
+        if self.newest.is_none() || self.frames_since_snapshot >= self.interval {
+            self.push(cpu);
+        }
+    }
+
+    /// Call each frame instead of emulating while rewinding, snapshots
+    /// are restored every `interval` frames so the game plays backwards
+    /// at normal speed. Returns false once there is nothing left to rewind.
+    pub fn rewind_frame(&mut self, cpu: &mut CPU) -> Result<bool, SaveStateError> {
+        if self.frames_until_step == 0 {
+            if !self.step_back(cpu)? {
+                return Ok(false);
+            }
+            self.frames_until_step = self.interval;
+        }
+        self.frames_until_step -= 1;
+        Ok(true)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{RewindBuffer, encode_delta, apply_delta};
+    use system::{CPU, Platform};
+    use system::test_util::setup_cpu;
+
+/* loop { V0 += 1, draw sprite 0 at V0,0 } */
+const PROGRAM : [u8; 6] = [0x70, 0x01, 0xD0, 0x15, 0x12, 0x00];
+
+fn run_frame(cpu: &mut CPU, rewind: &mut RewindBuffer) {
+    for _ in 0 .. 3 {
+        cpu.perform_cycle();
+    }
+    rewind.record_frame(cpu);
+}
+
+#[test]
+fn check_delta_round_trip() {
+    let old = [1u8, 2, 3, 4, 5, 6, 7, 8];
+    let new = [1u8, 9, 9, 4, 5, 6, 7, 0];
+    let delta = encode_delta(&old, &new);
+    let mut state = old;
+    apply_delta(&mut state, &delta);
+    assert_eq!(state, new);
+    apply_delta(&mut state, &delta);
+    assert_eq!(state, old);
+    assert!(encode_delta(&old, &old).len() <= 2);
+}
+
+#[test]
+fn check_steps_back_through_snapshots() {
+    let mut cpu = setup_cpu(Platform::default(), &PROGRAM);
+    let mut rewind = RewindBuffer::new(10, 1);
+    let mut rows = Vec::new();
+    for _ in 0 .. 5 {
+        run_frame(&mut cpu, &mut rewind);
+        rows.push(cpu.framebuffer().row(0));
+    }
+    assert_eq!(cpu.get_reg(0), 5);
+    assert_eq!(rewind.len(), 5);
+
+    for expected in (1 .. 6).rev() {
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.get_reg(0), expected);
+        assert_eq!(cpu.framebuffer().row(0), rows[expected as usize - 1]);
+    }
+    assert!(!rewind.step_back(&mut cpu).unwrap());
+    assert_eq!(cpu.get_reg(0), 1);
+
+    /* Recording continues from the restored state */
+    run_frame(&mut cpu, &mut rewind);
+    assert_eq!(cpu.get_reg(0), 2);
+    assert_eq!(rewind.len(), 1);
+}
+
+#[test]
+fn check_capacity_drops_oldest() {
+    let mut cpu = setup_cpu(Platform::default(), &PROGRAM);
+    let mut rewind = RewindBuffer::new(3, 1);
+    for _ in 0 .. 10 {
+        run_frame(&mut cpu, &mut rewind);
+    }
+    assert_eq!(rewind.len(), 3);
+    while rewind.step_back(&mut cpu).unwrap() {}
+    assert_eq!(cpu.get_reg(0), 8);
+
+    /* Only the newest snapshot is stored in full */
+    assert_eq!(rewind.stored_size(), 0);
+    for _ in 0 .. 3 {
+        run_frame(&mut cpu, &mut rewind);
+    }
+    assert!(rewind.stored_size() < cpu.save_state().len() + 200);
+}
+
+#[test]
+fn check_rewinds_at_playback_speed() {
+    let mut cpu = setup_cpu(Platform::default(), &PROGRAM);
+    let mut rewind = RewindBuffer::new(10, 2);
+    for _ in 0 .. 6 {
+        run_frame(&mut cpu, &mut rewind);
+    }
+    /* Snapshots at frames 1, 3 and 5 */
+    assert_eq!(rewind.len(), 3);
+
+    let mut values = Vec::new();
+    while rewind.rewind_frame(&mut cpu).unwrap() {
+        values.push(cpu.get_reg(0));
+    }
+    assert_eq!(values, vec![5, 5, 3, 3, 1, 1]);
+}
+
+#[test]
+fn check_failed_restore_reported() {
+    let mut cpu = setup_cpu(Platform::Schip11, &PROGRAM);
+    let mut rewind = RewindBuffer::new(10, 1);
+    for _ in 0 .. 3 {
+        run_frame(&mut cpu, &mut rewind);
+    }
+
+    /* Snapshots from another platform can't be loaded */
+    let mut other = setup_cpu(Platform::default(), &PROGRAM);
+    assert!(rewind.step_back(&mut other).is_err());
+    assert!(rewind.is_empty());
+    assert_eq!(other.get_reg(0), 0);
+}
+
+}
@@ -0,0 +1,516 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use super::{CPU, STACK_SIZE};
+use super::platform::Font;
+use super::audio::PATTERN_SIZE;
+use super::hash::{fnv1a, fnv1a_32};
+use super::graphics::{PLANE_COUNT, FRAMEBUFFER_ROWS};
+
+/// Bytes every save state starts with.
+pub const SAVE_STATE_MAGIC : &[u8; 8] = b"SCHIP8ST";
+
+/// Version of the format written by `CPU::save_state`. States written
+/// by older versions are converted when loaded, newer ones are rejected.
+pub const SAVE_STATE_VERSION : u16 = 2;
+
+/* Magic followed by the format version */
+const HEADER_SIZE : usize = 10;
+
+/* FNV-1a hash of everything before it, 32 bit in version 1 */
+const CHECKSUM_SIZE : usize = 8;
+const V1_CHECKSUM_SIZE : usize = 4;
+
+/* Quirks, stack depth, HP48 flag count, instruction sets,
+ * doubled low resolution and font. Not in version 1 */
+const PROFILE_SIZE : usize = 7;
+
+
+/// Reason a save state couldn't be loaded or saved.
+#[derive(Debug)]
+pub enum SaveStateError {
+    /// The data doesn't start with `SAVE_STATE_MAGIC`.
+    NotASaveState,
+    /// The state was written in a format version this build can't read.
+    UnsupportedVersion { version: u16 },
+    /// The data ends before the whole state has been read.
+    Truncated,
+    /// The data has been corrupted since it was saved.
+    ChecksumMismatch,
+    /// The state was saved from a machine with a different amount of memory,
+    /// usually because it was running as another platform.
+    MemorySizeMismatch { saved: usize, current: usize },
+    /// The state was saved from a machine emulating another platform,
+    /// with different quirks, instructions, stack depth, HP48 flags,
+    /// low resolution pixel size or font.
+    PlatformMismatch,
+    /// A saved value is out of range for the machine.
+    InvalidValue(&'static str),
+    /// Reading or writing the save state file failed.
+    Io(io::Error)
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveStateError::NotASaveState =>
+                write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion { version } =>
+                write!(f, "Save state format version {} is not supported, expected {} or older",
+                       version, SAVE_STATE_VERSION),
+            SaveStateError::Truncated =>
+                write!(f, "Save state is truncated"),
+            SaveStateError::ChecksumMismatch =>
+                write!(f, "Save state is corrupt, checksum doesn't match"),
+            SaveStateError::MemorySizeMismatch { saved, current } =>
+                write!(f, "Save state has {} bytes of memory but the machine has {}, \
+                           was it saved on another platform?", saved, current),
+            SaveStateError::PlatformMismatch =>
+                write!(f, "Save state was saved on another platform"),
+            SaveStateError::InvalidValue(field) =>
+                write!(f, "Save state has an invalid {}", field),
+            SaveStateError::Io(ref e) =>
+                write!(f, "Save state file error: {}", e),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(e: io::Error) -> SaveStateError {
+        SaveStateError::Io(e)
+    }
+}
+
+
+/* Reads little endian values from the body of a state */
+struct StateReader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> StateReader<'a> {
+
+    fn bytes(&mut self, len:usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn array<T: Default + AsMut<[u8]>>(&mut self) -> Result<T, SaveStateError> {
+        let mut array = T::default();
+        let len = array.as_mut().len();
+        array.as_mut().copy_from_slice(self.bytes(len)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self, field:&'static str) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::InvalidValue(field))
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn u128(&mut self) -> Result<u128, SaveStateError> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+}
+
+
+/* Platform settings a state depends on, it's only loaded into a CPU
+ * with the same settings. Bits must keep their meaning between versions */
+fn profile_bytes(cpu: &CPU) -> [u8; PROFILE_SIZE] {
+    let q = &cpu.quirks;
+    let quirks = [q.shift_uses_vy, q.load_store_increments_i, q.jump_uses_vx, q.logic_resets_vf,
+                  q.clip_sprites, q.display_wait, q.lores_scroll_halved, q.lores_dxy0_draws_8x16,
+                  q.vf_counts_rows, q.mode_switch_clears]
+        .iter().enumerate().fold(0u16, |bits, (i, &set)| bits | (set as u16) << i);
+    let instructions = [cpu.schip_instructions, cpu.scroll_up_instruction, cpu.xo_chip_instructions]
+        .iter().enumerate().fold(0u8, |bits, (i, &set)| bits | (set as u8) << i);
+    let font = match cpu.font {
+        Font::Vip => 0,
+        Font::Chip48 => 1
+    };
+    let quirks = quirks.to_le_bytes();
+    [quirks[0], quirks[1], cpu.stack_depth as u8, cpu.hp_flag_count as u8, instructions,
+     cpu.graphics.doubled_lores() as u8, font]
+}
+
+
+/* Everything in a save state, read in full and
+ * validated before any of it is applied */
+struct MachineState {
+    profile: Option<[u8; PROFILE_SIZE]>, /* Unknown for version 1 */
+    registers: [u8; 16],
+    index_reg: u16,
+    pc: u16,
+    sp: usize,
+    stack: [u16; STACK_SIZE],
+    delay_timer: u8,
+    sound_timer: u8,
+    hp_48_flags: [u8; 16],
+    mode: bool,
+    halt: bool,
+    waiting_for_vblank: bool,
+    audio_pattern: [u8; PATTERN_SIZE],
+    pitch: u8,
+    rng: u64,
+    planes: u8,
+    screen: [[u128; FRAMEBUFFER_ROWS]; PLANE_COUNT],
+    mem: Vec<u8>
+}
+
+fn read_v1(r: &mut StateReader) -> Result<MachineState, SaveStateError> {
+    let mem_size = r.u32()? as usize;
+    read_machine(r, mem_size, None)
+}
+
+fn read_v2(r: &mut StateReader) -> Result<MachineState, SaveStateError> {
+    let mem_size = r.u32()? as usize;
+    let profile = r.array()?;
+    read_machine(r, mem_size, Some(profile))
+}
+
+/* Everything after the profile, unchanged since version 1 */
+fn read_machine(r: &mut StateReader, mem_size: usize, profile: Option<[u8; PROFILE_SIZE]>)
+                -> Result<MachineState, SaveStateError> {
+    let mut state = MachineState {
+        profile,
+        registers: r.array()?,
+        index_reg: r.u16()?,
+        pc: r.u16()?,
+        sp: r.u8()? as usize,
+        stack: [0; STACK_SIZE],
+        delay_timer: 0,
+        sound_timer: 0,
+        hp_48_flags: [0; 16],
+        mode: false,
+        halt: false,
+        waiting_for_vblank: false,
+        audio_pattern: [0; PATTERN_SIZE],
+        pitch: 0,
+        rng: 0,
+        planes: 0,
+        screen: [[0; FRAMEBUFFER_ROWS]; PLANE_COUNT],
+        mem: Vec::new()
+    };
+    for frame in state.stack.iter_mut() {
+        *frame = r.u16()?;
+    }
+    state.delay_timer = r.u8()?;
+    state.sound_timer = r.u8()?;
+    state.hp_48_flags = r.array()?;
+    state.mode = r.bool("resolution")?;
+    state.halt = r.bool("halt flag")?;
+    state.waiting_for_vblank = r.bool("vblank wait flag")?;
+    state.audio_pattern = r.array()?;
+    state.pitch = r.u8()?;
+    state.rng = r.u64()?;
+    state.planes = r.u8()?;
+    for plane in state.screen.iter_mut() {
+        for row in plane.iter_mut() {
+            *row = r.u128()?;
+        }
+    }
+    state.mem = r.bytes(mem_size)?.to_vec();
+    Ok(state)
+}
+
+
+impl CPU {
+
+    /// Snapshot the whole machine: registers, memory, stack, timers,
+    /// flags, framebuffer and random number generator. The platform's
+    /// quirks and limits are recorded so the state can only be loaded
+    /// into a CPU emulating the same platform.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + self.mem.len() + 2048);
+        out.extend_from_slice(SAVE_STATE_MAGIC);
+        out.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+
+        out.extend_from_slice(&(self.mem.len() as u32).to_le_bytes());
+        out.extend_from_slice(&profile_bytes(self));
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.index_reg.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.push(self.sp as u8);
+        for frame in self.stack.iter() {
+            out.extend_from_slice(&frame.to_le_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.hp_48_flags);
+        out.push(self.mode as u8);
+        out.push(self.halt as u8);
+        out.push(self.waiting_for_vblank as u8);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+        out.push(self.graphics.selected_plane_mask());
+        for plane in 0 .. PLANE_COUNT {
+            for y in 0 .. FRAMEBUFFER_ROWS {
+                out.extend_from_slice(&self.graphics.plane_row(plane, y).to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&self.mem);
+
+        let checksum = fnv1a(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Restore a snapshot taken with `save_state`. The state is fully
+    /// validated first, on error the machine is left unchanged.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        if data.len() < SAVE_STATE_MAGIC.len() || &data[.. SAVE_STATE_MAGIC.len()] != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::NotASaveState);
+        }
+        if data.len() < HEADER_SIZE {
+            return Err(SaveStateError::Truncated);
+        }
+
+        let version = u16::from_le_bytes([data[8], data[9]]);
+        if version == 0 || version > SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion { version });
+        }
+
+        let checksum_size = if version == 1 {V1_CHECKSUM_SIZE} else {CHECKSUM_SIZE};
+        if data.len() < HEADER_SIZE + checksum_size {
+            return Err(SaveStateError::Truncated);
+        }
+        let (contents, checksum) = data.split_at(data.len() - checksum_size);
+        let valid = if version == 1 {
+            fnv1a_32(contents).to_le_bytes() == checksum
+        } else {
+            fnv1a(contents).to_le_bytes() == checksum
+        };
+        if !valid {
+            return Err(SaveStateError::ChecksumMismatch);
+        }
+
+        /* When the format changes older versions keep their own
+         * read function, leaving out anything they didn't save */
+        let mut reader = StateReader { data: &contents[HEADER_SIZE ..] };
+        let state = if version == 1 {read_v1(&mut reader)?} else {read_v2(&mut reader)?};
+        if !reader.data.is_empty() {
+            return Err(SaveStateError::InvalidValue("length"));
+        }
+
+        if state.mem.len() != self.mem.len() {
+            return Err(SaveStateError::MemorySizeMismatch { saved: state.mem.len(),
+                                                            current: self.mem.len() });
+        }
+        if let Some(profile) = state.profile {
+            if profile != profile_bytes(self) {
+                return Err(SaveStateError::PlatformMismatch);
+            }
+        }
+        if state.sp > self.stack_depth {
+            return Err(SaveStateError::InvalidValue("stack pointer"));
+        }
+        if state.planes >> PLANE_COUNT != 0 {
+            return Err(SaveStateError::InvalidValue("plane selection"));
+        }
+
+        self.registers = state.registers;
+        self.index_reg = state.index_reg;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.hp_48_flags = state.hp_48_flags;
+        self.mode = state.mode;
+        self.halt = state.halt;
+        self.waiting_for_vblank = state.waiting_for_vblank;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.rng.set_state(state.rng);
+        self.mem = state.mem;
+
+        self.graphics.set_mode(state.mode);
+        self.graphics.select_planes(state.planes);
+        for (plane, rows) in state.screen.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                self.graphics.set_plane_row(plane, y, *row);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save a snapshot of the machine to a file.
+    pub fn save_state_file<P: AsRef<Path>>(&self, file_path: P) -> Result<(), SaveStateError> {
+        let mut file = File::create(file_path)?;
+        file.write_all(&self.save_state())?;
+        Ok(())
+    }
+
+    /// Restore a snapshot saved with `save_state_file`.
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<(), SaveStateError> {
+        let mut data = Vec::new();
+        File::open(file_path)?.read_to_end(&mut data)?;
+        self.load_state(&data)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{SaveStateError, SAVE_STATE_VERSION, HEADER_SIZE, CHECKSUM_SIZE, PROFILE_SIZE};
+    use system::{Platform, Profile};
+    use system::platform::Font;
+    use system::hash::fnv1a_32;
+    use system::test_util::{setup_cpu, setup_cpu_with_profile};
+    use rom;
+
+/* 00FF hires, V0 = 5, delay = V0, I = sprite 5, draw at 0,0,
+ * call 0x210, loop. 0x210: loop { V1 = random } */
+const PROGRAM : [u8; 20] = [0x00, 0xFF, 0x60, 0x05, 0xF0, 0x15, 0xF0, 0x29,
+                            0xD1, 0x15, 0x22, 0x10, 0x12, 0x0C, 0x00, 0x00,
+                            0xC1, 0xFF, 0x12, 0x10];
+
+#[test]
+fn check_state_round_trip() {
+    let mut cpu = setup_cpu(Platform::SchipModern, &PROGRAM);
+    for _ in 0 .. 6 {
+        cpu.perform_cycle();
+    }
+    let state = cpu.save_state();
+
+    /* Keep running to diverge from the saved state */
+    for _ in 0 .. 6 {
+        cpu.perform_cycle();
+    }
+    cpu.tick_timers();
+    let expected_random = cpu.get_reg(1);
+
+    let mut restored = setup_cpu(Platform::SchipModern, &[]);
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.get_pc(), 0x210);
+    assert_eq!(restored.get_delay_timer(), 5);
+    assert_eq!(restored.get_reg(0), 5);
+    assert_eq!(restored.get_mem(0x200), 0x00);
+    assert_eq!(restored.get_mem(0x201), 0xFF);
+    assert_eq!(restored.framebuffer().width(), 128);
+    assert_eq!(&restored.framebuffer().row(0)[.. 5], &[1, 1, 1, 1, 0]);
+    assert_eq!(restored.save_state(), state);
+
+    /* Random numbers continue from the same point */
+    for _ in 0 .. 6 {
+        restored.perform_cycle();
+    }
+    assert_eq!(restored.get_reg(1), expected_random);
+}
+
+#[test]
+fn check_corrupt_states_rejected() {
+    let cpu = setup_cpu(Platform::SchipModern, &PROGRAM);
+    let state = cpu.save_state();
+    let mut target = setup_cpu(Platform::SchipModern, &[0x12, 0x00]);
+
+    match target.load_state(b"not a state") {
+        Err(SaveStateError::NotASaveState) => {},
+        r => panic!("unexpected {:?}", r)
+    }
+
+    let mut newer = state.clone();
+    newer[8] = (SAVE_STATE_VERSION + 1) as u8;
+    match target.load_state(&newer) {
+        Err(SaveStateError::UnsupportedVersion { version }) =>
+            assert_eq!(version, SAVE_STATE_VERSION + 1),
+        r => panic!("unexpected {:?}", r)
+    }
+
+    let mut corrupt = state.clone();
+    corrupt[100] ^= 1;
+    match target.load_state(&corrupt) {
+        Err(SaveStateError::ChecksumMismatch) => {},
+        r => panic!("unexpected {:?}", r)
+    }
+
+    match target.load_state(&state[.. 12]) {
+        Err(SaveStateError::Truncated) => {},
+        r => panic!("unexpected {:?}", r)
+    }
+
+    /* Failed loads leave the machine untouched */
+    assert_eq!(target.get_mem(0x200), 0x12);
+}
+
+#[test]
+fn check_platform_mismatch_rejected() {
+    let state = setup_cpu(Platform::XoChip, &PROGRAM).save_state();
+    let mut cpu = setup_cpu(Platform::SchipModern, &PROGRAM);
+    match cpu.load_state(&state) {
+        Err(SaveStateError::MemorySizeMismatch { saved, current }) => {
+            assert_eq!(saved, rom::XO_MAX_RAM);
+            assert_eq!(current, rom::MAX_RAM);
+        },
+        r => panic!("unexpected {:?}", r)
+    }
+
+    /* Same amount of memory but different quirks */
+    let state = setup_cpu(Platform::Schip11, &PROGRAM).save_state();
+    match cpu.load_state(&state) {
+        Err(SaveStateError::PlatformMismatch) => {},
+        r => panic!("unexpected {:?}", r)
+    }
+    assert!(setup_cpu(Platform::Schip11, &[]).load_state(&state).is_ok());
+
+    /* Pixel size and font aren't quirks but still differ */
+    let profile = Platform::SchipModern.profile();
+    for &other in &[Profile { doubled_lores: true, .. profile },
+                    Profile { font: Font::Vip, .. profile }] {
+        let state = setup_cpu_with_profile(other, &PROGRAM).save_state();
+        match cpu.load_state(&state) {
+            Err(SaveStateError::PlatformMismatch) => {},
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+}
+
+#[test]
+fn check_version_1_state_loads() {
+    let mut cpu = setup_cpu(Platform::SchipModern, &PROGRAM);
+    for _ in 0 .. 6 {
+        cpu.perform_cycle();
+    }
+    let state = cpu.save_state();
+
+    /* Version 1 had no profile and a 32 bit checksum */
+    let profile_start = HEADER_SIZE + 4;
+    let mut old = state[.. profile_start].to_vec();
+    old.extend_from_slice(&state[profile_start + PROFILE_SIZE .. state.len() - CHECKSUM_SIZE]);
+    old[8] = 1;
+    let checksum = fnv1a_32(&old);
+    old.extend_from_slice(&checksum.to_le_bytes());
+
+    let mut restored = setup_cpu(Platform::SchipModern, &[]);
+    restored.load_state(&old).unwrap();
+    assert_eq!(restored.save_state(), state);
+}
+
+}
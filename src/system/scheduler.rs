@@ -0,0 +1,263 @@
+extern crate time;
+
+use std::thread;
+use std::time::Duration;
+use super::{CPU, CpuError, StepOutcome, TIMER_FREQUENCY};
+
+const NS_PER_SEC : u64 = 1000000000;
+
+/* How many frames the scheduler can fall behind before giving up
+ * on catching up and resynchronising with the clock */
+const MAX_FRAME_LAG : u64 = 5;
+
+
+/// Source of time used to pace emulation.
+pub trait Clock {
+    /// Monotonically increasing time in nanoseconds.
+    fn now_ns(&mut self) -> u64;
+    /// Block for the given number of nanoseconds.
+    fn sleep_ns(&mut self, ns: u64);
+}
+
+
+/// Clock backed by the system's high resolution timer.
+pub struct SystemClock;
+
+impl SystemClock {
+
+    pub fn new() -> SystemClock {
+        SystemClock
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+
+    fn now_ns(&mut self) -> u64 {
+        time::precise_time_ns()
+    }
+
+    fn sleep_ns(&mut self, ns: u64) {
+        thread::sleep(Duration::new(ns / NS_PER_SEC, (ns % NS_PER_SEC) as u32));
+    }
+}
+
+
+/// Runs the CPU in 60Hz frames. Each frame executes the instructions
+/// due for that frame, ticks the timers and presents the display once.
+/// When the instruction rate isn't a multiple of 60 the fraction left
+/// over is carried to the next frame, so a second always executes
+/// exactly the requested number of instructions.
+///
+/// Frame deadlines are computed from the time the first frame started
+/// rather than from the end of the previous frame, so rounding and
+/// oversleeping don't accumulate into drift.
+pub struct Scheduler {
+    clock: Box<dyn Clock>,
+    instructions_per_second: u64,
+    instruction_remainder: u64, /* Instructions per second carried over, under 60 */
+    start_ns: Option<u64>, /* Time the current run of frames started */
+    frame_count: u64 /* Frames completed since start_ns */
+}
+
+impl Scheduler {
+
+    /// Create a scheduler executing the given number of instructions
+    /// per second, at least one.
+    pub fn new(instructions_per_second: u64, clock: Box<dyn Clock>) -> Scheduler {
+        Scheduler { clock,
+                    instructions_per_second:
+                        if instructions_per_second > 0 {instructions_per_second} else {1},
+                    instruction_remainder: 0,
+                    start_ns: None,
+                    frame_count: 0
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> u64 {
+        self.instructions_per_second
+    }
+
+    /// Emulate a single frame without waiting. Execution stops
+    /// early if the program exits, faults or waits for the
+    /// next frame to draw.
+    pub fn run_frame(&mut self, cpu: &mut CPU) -> Result<StepOutcome, CpuError> {
+        let outcome = self.run_instructions(cpu)?;
+        self.end_frame(cpu);
+        Ok(outcome)
+    }
+
+    /// Execute the instructions of a frame, the first half of
+    /// `run_frame`. The sound for the frame can be taken from
+    /// the CPU before calling `end_frame`.
+    pub fn run_instructions(&mut self, cpu: &mut CPU) -> Result<StepOutcome, CpuError> {
+        let mut outcome = StepOutcome::Continue;
+
+        let due = self.instruction_remainder + self.instructions_per_second;
+        self.instruction_remainder = due % TIMER_FREQUENCY;
+
+        for _ in 0 .. due / TIMER_FREQUENCY {
+            outcome = cpu.try_perform_cycle()?;
+            if outcome != StepOutcome::Continue {
+                break;
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Tick the timers and present the display at the end of a frame.
+    pub fn end_frame(&mut self, cpu: &mut CPU) {
+        cpu.tick_timers();
+        cpu.present();
+    }
+
+    /// Sleep until the next frame is due. If emulation has fallen more
+    /// than a few frames behind the pacing is restarted from now
+    /// instead of running frames back to back to catch up.
+    pub fn wait_for_next_frame(&mut self) {
+        let now = self.clock.now_ns();
+        let start = match self.start_ns {
+            Some(start) => start,
+            None => {
+                self.start_ns = Some(now);
+                self.frame_count = 0;
+                now
+            }
+        };
+
+        self.frame_count += 1;
+        let deadline = start + (self.frame_count * NS_PER_SEC) / TIMER_FREQUENCY;
+
+        if now < deadline {
+            self.clock.sleep_ns(deadline - now);
+        } else if now - deadline > (MAX_FRAME_LAG * NS_PER_SEC) / TIMER_FREQUENCY {
+            self.start_ns = Some(now);
+            self.frame_count = 0;
+        }
+    }
+
+    /// Run frames at 60Hz until the program exits or faults.
+    pub fn run(&mut self, cpu: &mut CPU) -> Result<(), CpuError> {
+        self.start_ns = None;
+        while self.run_frame(cpu)? != StepOutcome::Halted {
+            self.wait_for_next_frame();
+        }
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, Scheduler, NS_PER_SEC};
+    use system::Platform;
+    use system::test_util::setup_cpu;
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+/* Clock which only advances when slept on or
+ * explicitly moved forward by the test */
+#[derive(Clone)]
+struct FakeClock {
+    now: Rc<Cell<u64>>,
+    slept: Rc<Cell<u64>>
+}
+
+impl FakeClock {
+    fn new() -> FakeClock {
+        FakeClock { now: Rc::new(Cell::new(1000)), slept: Rc::new(Cell::new(0)) }
+    }
+
+    fn advance(&self, ns: u64) {
+        self.now.set(self.now.get() + ns);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now_ns(&mut self) -> u64 {
+        self.now.get()
+    }
+
+    fn sleep_ns(&mut self, ns: u64) {
+        self.advance(ns);
+        self.slept.set(self.slept.get() + ns);
+    }
+}
+
+
+#[test]
+fn check_frame_runs_instructions_and_ticks_timers() {
+    /* V0 = 5, delay = V0, loop { V1 += 1 } */
+    let mut cpu = setup_cpu(Platform::default(),
+                            &[0x60, 0x05, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04]);
+    let mut scheduler = Scheduler::new(600, Box::new(FakeClock::new()));
+
+    scheduler.run_frame(&mut cpu).unwrap();
+    assert_eq!(cpu.get_reg(1), 4);
+    assert_eq!(cpu.get_delay_timer(), 4);
+}
+
+#[test]
+fn check_fractional_instructions_per_frame() {
+    /* loop { V1 += 1 }, 500 a second is 8 1/3 instructions a frame */
+    let mut cpu = setup_cpu(Platform::default(), &[0x71, 0x01, 0x12, 0x00]);
+    let mut scheduler = Scheduler::new(500, Box::new(FakeClock::new()));
+    scheduler.run_frame(&mut cpu).unwrap();
+    assert_eq!(cpu.get_reg(1), 4);
+
+    /* The remainder is carried so a second runs all 500 */
+    for _ in 1 .. 60 {
+        scheduler.run_frame(&mut cpu).unwrap();
+    }
+    assert_eq!(cpu.get_reg(1), 250);
+}
+
+#[test]
+fn check_pacing_has_no_drift() {
+    let clock = FakeClock::new();
+    let mut scheduler = Scheduler::new(600, Box::new(clock.clone()));
+    let start = clock.now.get();
+
+    for _ in 0 .. 60 {
+        clock.advance(1000); /* time spent emulating the frame */
+        scheduler.wait_for_next_frame();
+    }
+
+    /* 1/60th of a second isn't a whole number of nanoseconds,
+     * 60 frames should still take exactly a second */
+    assert_eq!(clock.now.get() - start, NS_PER_SEC + 1000);
+}
+
+#[test]
+fn check_resync_after_falling_behind() {
+    let clock = FakeClock::new();
+    let mut scheduler = Scheduler::new(600, Box::new(clock.clone()));
+
+    scheduler.wait_for_next_frame();
+    clock.advance(NS_PER_SEC); /* stall for a second */
+    scheduler.wait_for_next_frame();
+    assert_eq!(clock.slept.get(), NS_PER_SEC / 60);
+
+    /* Next frame should be paced from after the stall rather
+     * than trying to catch up the missed frames */
+    clock.slept.set(0);
+    scheduler.wait_for_next_frame();
+    assert_eq!(clock.slept.get(), NS_PER_SEC / 60);
+}
+
+#[test]
+fn check_run_until_exit() {
+    /* 00FD exit */
+    let mut cpu = setup_cpu(Platform::default(), &[0x00, 0xFD]);
+    let mut scheduler = Scheduler::new(600, Box::new(FakeClock::new()));
+    assert_eq!(scheduler.run(&mut cpu), Ok(()));
+    assert!(cpu.is_finished());
+}
+
+}
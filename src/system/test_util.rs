@@ -0,0 +1,42 @@
+//! Fixtures shared by the unit tests.
+
+use super::{CPU, Platform, Profile, Quirks};
+use super::graphics::graphics_null::NullScreen;
+use super::io::Input;
+use super::io::mock_io::MockIO;
+use rom;
+
+/// CPU emulating the given platform with the program loaded at 0x200.
+pub fn setup_cpu(platform: Platform, program: &[u8]) -> CPU {
+    setup_cpu_with_input(platform, program, Box::new(MockIO::new()))
+}
+
+/// As `setup_cpu`, reading keys from the given input.
+pub fn setup_cpu_with_input(platform: Platform, program: &[u8], input: Box<dyn Input>) -> CPU {
+    let memory_size = platform.profile().memory_size;
+    CPU::with_platform(rom::load_rom(program, memory_size).unwrap(), platform,
+                       Box::new(NullScreen::new()), input)
+}
+
+/// CPU with a custom platform profile.
+pub fn setup_cpu_with_profile(profile: Profile, program: &[u8]) -> CPU {
+    CPU::with_profile(rom::load_rom(program, profile.memory_size).unwrap(), profile,
+                      Box::new(NullScreen::new()), Box::new(MockIO::new()))
+}
+
+/// CPU emulating the default platform with other quirks.
+pub fn setup_cpu_with_quirks(quirks: Quirks, program: &[u8]) -> CPU {
+    CPU::new(rom::load_rom(program, rom::MAX_RAM).unwrap(), quirks,
+             Box::new(NullScreen::new()), Box::new(MockIO::new()))
+}
+
+/// CPU emulating the default platform without quirks and an empty program.
+pub fn setup_blank_cpu() -> CPU {
+    setup_cpu_with_keypad(MockIO::new())
+}
+
+/// Blank CPU reading keys from the given keypad.
+pub fn setup_cpu_with_keypad(keypad: MockIO) -> CPU {
+    CPU::new(rom::load_rom(&[], rom::MAX_RAM).unwrap(), Quirks::default(),
+             Box::new(NullScreen::new()), Box::new(keypad))
+}